@@ -4,12 +4,12 @@ param (
     [string]$mode = "sparse" # New parameter with default value
 )
 
-# Define the path to the CSV file
-$csvPath = "./shared/nodes.csv"
+# Define the path to the committee file
+$committeePath = "./shared/committee.toml"
 
 # Check if the file exists
-if (-not (Test-Path $csvPath)) {
-    Write-Host "nodes.csv not found in shared/. Please generate it first."
+if (-not (Test-Path $committeePath)) {
+    Write-Host "committee.toml not found in shared/. Please generate it first with gen-committee."
     exit 1
 }
 
@@ -20,8 +20,8 @@ if (-not $transaction_size -or -not $n_transactions) {
     exit 1
 }
 
-# Skip the header and read all lines
-$lines = Get-Content $csvPath | Select-Object -Skip 1
+# One [[node]] table per node
+$tables = (Get-Content $committeePath -Raw) -split "\[\[node\]\]" | Select-Object -Skip 1
 
 Write-Host "--------------------------------------------------"
 Write-Host " 🚀 STARTING EXPERIMENT"
@@ -31,19 +31,16 @@ Write-Host "   Tx Size:   $transaction_size bytes"
 Write-Host "   Tx Count:  $n_transactions per block"
 Write-Host "--------------------------------------------------"
 
-foreach ($line in $lines) {
-    $parts = $line -split ","
-    $id = $parts[0].Trim()
-    $hostname = $parts[1].Trim()
-    $port = $parts[2].Trim()
+foreach ($table in $tables) {
+    $id = [regex]::Match($table, '(?m)^\s*id\s*=\s*(\d+)').Groups[1].Value
+    $hostname = [regex]::Match($table, '(?m)^\s*host\s*=\s*"([^"]*)"').Groups[1].Value
+    $port = [regex]::Match($table, '(?m)^\s*port\s*=\s*(\d+)').Groups[1].Value
 
     Write-Host "Starting node ${id} on ${hostname}:${port}..."
 
-    $allArgs = "$id $transaction_size $n_transactions"
-    
-    # We construct a command that sets the env var *inside* the new shell, then runs cargo.
-    # Note the backtick ` before $env:PROTOCOL to prevent the current shell from expanding it.
-    $commandString = "`$env:PROTOCOL='$mode'; cargo run --release --package sparse_bullshark --bin sparse_bullshark $allArgs"
+    $allArgs = "run --node-id $id --tx-size $transaction_size --tx-count $n_transactions --protocol $mode"
+
+    $commandString = "cargo run --release --package sparse_bullshark --bin sparse_bullshark -- $allArgs"
 
     if ($IsLinux) {
         # Use 'pwsh' on Linux
@@ -0,0 +1,16 @@
+use std::collections::HashMap;
+use ed25519_dalek::PublicKey;
+use crate::domain::node::Node;
+
+/// The members of a committee and their keys, checked to describe the same
+/// set of nodes. See `initializer::load_committee`.
+#[derive(Debug, Clone)]
+pub struct Committee {
+    pub nodes: Vec<Node>,
+    pub public_keys: HashMap<u32, PublicKey>,
+    /// The optional `bls_public_key` entries as raw bytes; parsing them is
+    /// left to the BLS backend.
+    pub bls_public_keys: HashMap<u32, Vec<u8>>,
+    /// The optional `bls_proof_of_possession` entries, raw as well.
+    pub bls_proofs_of_possession: HashMap<u32, Vec<u8>>,
+}
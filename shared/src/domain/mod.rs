@@ -1,3 +1,4 @@
 pub mod transaction;
 pub mod node;
-pub mod environment;
\ No newline at end of file
+pub mod environment;
+pub mod committee;
\ No newline at end of file
@@ -1,7 +1,16 @@
+/// Stake of a node that doesn't list one.
+pub const DEFAULT_STAKE: u64 = 1;
 
 #[derive(Debug, serde::Deserialize, Clone)]
 pub struct Node {
     pub id: u32,
     pub host: String,
     pub port: u16,
+    /// Voting weight. Recorded only: quorums still count nodes.
+    #[serde(default = "default_stake")]
+    pub stake: u64,
+}
+
+fn default_stake() -> u64 {
+    DEFAULT_STAKE
 }
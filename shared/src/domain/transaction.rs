@@ -1,13 +1,10 @@
 use std::hash::{Hash};
 
+/// A command for the replicated key-value store. Commands are applied in the
+/// order consensus delivers their blocks.
 #[derive(Hash, Eq, PartialEq, Debug, Clone, serde::Serialize, serde::Deserialize)]
-pub struct Transaction {
-    pub padding: String,
-}
-
-impl Transaction {
-    pub fn new(padding_size: usize) -> Self {
-        let padding = "X".repeat(padding_size);
-        Transaction { padding }
-    }
+pub enum Transaction {
+    Put { key: String, value: String },
+    Get { key: String },
+    Delete { key: String },
 }
@@ -0,0 +1,64 @@
+//! The hash function used for everything the nodes have to agree on. It is
+//! chosen at build time: SHA-256 by default, BLAKE3 with the `blake3` feature.
+//! Every node of a committee must be built with the same choice; nodes
+//! exchange `NAME` when connecting and refuse a peer hashing differently.
+
+/// A 32-byte hash function.
+pub trait Hasher: Default {
+    /// Name of the function, for logs and test vectors.
+    const NAME: &'static str;
+
+    fn update(&mut self, data: &[u8]);
+
+    fn finalize(self) -> [u8; 32];
+
+    fn chain_update(mut self, data: impl AsRef<[u8]>) -> Self {
+        self.update(data.as_ref());
+        self
+    }
+
+    /// Hashes `data` in one go.
+    fn digest(data: &[u8]) -> [u8; 32] {
+        let mut hasher = Self::default();
+        hasher.update(data);
+        hasher.finalize()
+    }
+}
+
+#[derive(Default)]
+pub struct Sha256Hasher(sha2::Sha256);
+
+impl Hasher for Sha256Hasher {
+    const NAME: &'static str = "sha256";
+
+    fn update(&mut self, data: &[u8]) {
+        sha2::Digest::update(&mut self.0, data);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        sha2::Digest::finalize(self.0).into()
+    }
+}
+
+#[cfg(feature = "blake3")]
+#[derive(Default)]
+pub struct Blake3Hasher(blake3::Hasher);
+
+#[cfg(feature = "blake3")]
+impl Hasher for Blake3Hasher {
+    const NAME: &'static str = "blake3";
+
+    fn update(&mut self, data: &[u8]) {
+        self.0.update(data);
+    }
+
+    fn finalize(self) -> [u8; 32] {
+        self.0.finalize().into()
+    }
+}
+
+/// The hash function this build uses.
+#[cfg(not(feature = "blake3"))]
+pub type DefaultHasher = Sha256Hasher;
+#[cfg(feature = "blake3")]
+pub type DefaultHasher = Blake3Hasher;
@@ -1,40 +1,61 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error::Error;
-use std::{env, fs};
+use std::fs;
+use std::path::Path;
 use std::fs::File;
 use csv::ReaderBuilder;
+use serde::Deserialize;
 use toml::Value;
 use base64::{engine::general_purpose, Engine as _};
-use ed25519_dalek::{Keypair, PublicKey};
+use ed25519_dalek::PublicKey;
+use crate::domain::committee::Committee;
 use crate::domain::environment::Environment;
-use crate::domain::node::Node;
+use crate::domain::node::{Node, DEFAULT_STAKE};
 
 pub const MINUTES_PER_HOUR: u32 = 60;
 pub const SECONDS_PER_HOUR: u32 = 3600;
 pub const SECONDS_PER_MINUTE: u32 = 60;
 pub const ADD_ONE_MINUTE: u32 = 1;
 pub const ADD_ONE_HOUR: u32 = 1;
-const MIN_ARGS: usize = 4;
-const MY_NODE_ID_ARG_POS: usize = 1;
-const TRANSACTION_SIZE_ARG_POS: usize = 2;
-const N_TRANSACTIONS_ARG_POS: usize = 3;
-const NODES_FILENAME: &str = "./shared/nodes.csv";
-const PUBLIC_KEYS_FILENAME: &str = "./shared/public_keys.toml";
+pub const COMMITTEE_FILENAME: &str = "./shared/committee.toml";
+/// The committee used to be split into an address file and a key file. Both
+/// are still accepted, see `load_legacy_committee`.
+pub const NODES_FILENAME: &str = "./shared/nodes.csv";
+pub const PUBLIC_KEYS_FILENAME: &str = "./shared/public_keys.toml";
 const PUBLIC_KEYS_FILE_INDEX: &str = "public_key";
-const PRIVATE_KEY_ENV: &str = "PRIVATE_KEY_";
+const BLS_PUBLIC_KEYS_FILE_INDEX: &str = "bls_public_key";
+const BLS_PROOFS_OF_POSSESSION_FILE_INDEX: &str = "bls_proof_of_possession";
 
+/// The committee file: one `[[node]]` table per member.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommitteeFile {
+    node: Vec<CommitteeEntry>,
+}
 
-pub fn get_environment(args: Vec<String>) -> Result<Environment, Box<dyn Error>> {
-    if args.len() < MIN_ARGS {
-        return Err("Usage: simplex [my_node_id] [transaction_size] [number of transactions] [protocol_mode: optional] [test_flag: optional]".into());
-    }
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct CommitteeEntry {
+    id: u32,
+    host: String,
+    port: u16,
+    stake: Option<u64>,
+    public_key: String,
+    bls_public_key: Option<String>,
+    bls_proof_of_possession: Option<String>,
+}
 
-    let my_id = args[MY_NODE_ID_ARG_POS].parse::<u32>()?;
-    let transaction_size = args[TRANSACTION_SIZE_ARG_POS].parse::<usize>()?;
-    let n_transactions = args[N_TRANSACTIONS_ARG_POS].parse::<usize>()?;
-    let test_flag = false;//false no sigs true sigs
-    //let test_flag = args.iter().any(|arg| arg == "test");
-    let nodes = read_nodes_from_csv(NODES_FILENAME)?;
+
+/// The environment of node `my_id` in `committee`.
+/// With `test_flag` set, messages are neither signed nor verified.
+pub fn get_environment(
+    my_id: u32,
+    transaction_size: usize,
+    n_transactions: usize,
+    test_flag: bool,
+    committee: &Committee,
+) -> Result<Environment, Box<dyn Error>> {
+    let nodes = committee.nodes.clone();
     let my_node = nodes.iter().find(|node| node.id == my_id).ok_or("This process' node was not found")?.clone();
 
     Ok(Environment {
@@ -46,40 +67,286 @@ pub fn get_environment(args: Vec<String>) -> Result<Environment, Box<dyn Error>>
     })
 }
 
-pub fn read_nodes_from_csv(file_path: &str) -> Result<Vec<Node>, Box<dyn Error>> {
-    let file = File::open(file_path)?;
+/// Reads and checks the committee file at `file_path`.
+pub fn load_committee(file_path: &Path) -> Result<Committee, Box<dyn Error>> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| format!("failed to read committee file {}: {}", file_path.display(), e))?;
+    let file: CommitteeFile = toml::from_str(&content)
+        .map_err(|e| format!("invalid committee file {}: {}", file_path.display(), e))?;
+    let mut committee = Committee {
+        nodes: Vec::new(),
+        public_keys: HashMap::new(),
+        bls_public_keys: HashMap::new(),
+        bls_proofs_of_possession: HashMap::new(),
+    };
+    for entry in file.node {
+        let id = entry.id;
+        if committee.public_keys.insert(id, decode_public_key(id, &entry.public_key)?).is_some() {
+            return Err(format!("node {} is listed twice in {}", id, file_path.display()).into());
+        }
+        if let Some(bls_public_key) = entry.bls_public_key {
+            committee.bls_public_keys.insert(id, decode_base64(id, &bls_public_key)?);
+        }
+        if let Some(proof) = entry.bls_proof_of_possession {
+            committee.bls_proofs_of_possession.insert(id, decode_base64(id, &proof)?);
+        }
+        committee.nodes.push(Node { id, host: entry.host, port: entry.port, stake: entry.stake.unwrap_or(DEFAULT_STAKE) });
+    }
+    check_committee(&committee).map_err(|e| format!("invalid committee file {}: {}", file_path.display(), e))?;
+    Ok(committee)
+}
+
+/// Reads the committee from the old pair of a nodes CSV and a public keys
+/// TOML, and checks that they describe the same nodes.
+pub fn load_legacy_committee(nodes_file: &Path, public_keys_file: &Path) -> Result<Committee, Box<dyn Error>> {
+    let committee = Committee {
+        nodes: read_nodes_from_csv(nodes_file)?,
+        public_keys: get_public_keys(public_keys_file)?,
+        bls_public_keys: get_bls_public_keys(public_keys_file)?,
+        bls_proofs_of_possession: get_bls_proofs_of_possession(public_keys_file)?,
+    };
+    check_committee(&committee).map_err(|e| {
+        format!("{} and {} don't match: {}", nodes_file.display(), public_keys_file.display(), e)
+    })?;
+    Ok(committee)
+}
+
+/// Ids and addresses are unique, and every node, and only those, has a public key.
+fn check_committee(committee: &Committee) -> Result<(), String> {
+    if committee.nodes.is_empty() {
+        return Err("the committee has no nodes".to_string());
+    }
+    let mut ids = HashSet::new();
+    let mut addresses = HashSet::new();
+    for node in &committee.nodes {
+        if !ids.insert(node.id) {
+            return Err(format!("node {} is listed twice", node.id));
+        }
+        if !addresses.insert((node.host.as_str(), node.port)) {
+            return Err(format!("node {} reuses the address {}:{}", node.id, node.host, node.port));
+        }
+        if node.stake == 0 {
+            return Err(format!("node {} has no stake", node.id));
+        }
+        if !committee.public_keys.contains_key(&node.id) {
+            return Err(format!("node {} has no public key", node.id));
+        }
+    }
+    let keys = committee.public_keys.keys()
+        .chain(committee.bls_public_keys.keys())
+        .chain(committee.bls_proofs_of_possession.keys());
+    if let Some(id) = keys.filter(|id| !ids.contains(*id)).min() {
+        return Err(format!("there is a key entry for node {}, which is not in the committee", id));
+    }
+    Ok(())
+}
+
+pub fn read_nodes_from_csv(file_path: &Path) -> Result<Vec<Node>, Box<dyn Error>> {
+    let file = File::open(file_path).map_err(|e| format!("failed to read {}: {}", file_path.display(), e))?;
     let mut rdr = ReaderBuilder::new()
         .has_headers(true)
         .from_reader(file);
 
     let mut nodes = Vec::new();
     for result in rdr.deserialize() {
-        match result {
-            Ok(node) => nodes.push(node),
-            Err(e) => eprintln!("Error parsing CSV line: {}", e),
-        }
+        nodes.push(result.map_err(|e| format!("invalid line in {}: {}", file_path.display(), e))?);
     }
     Ok(nodes)
 }
 
-pub fn get_public_keys() -> HashMap<u32, PublicKey> {
-    let content = fs::read_to_string(PUBLIC_KEYS_FILENAME).expect("Failed to read public key file");
-    let data: Value = content.parse::<Value>().expect("Failed to parse TOML data");
-    let data_table = data.as_table().expect("Expected TOML data to be a table");
+pub fn get_public_keys(file_path: &Path) -> Result<HashMap<u32, PublicKey>, Box<dyn Error>> {
     let mut public_keys = HashMap::new();
+    for (id, public_key) in read_key_entries(file_path, PUBLIC_KEYS_FILE_INDEX)? {
+        public_keys.insert(id, decode_public_key(id, &public_key)?);
+    }
+    Ok(public_keys)
+}
+
+/// Reads the optional `bls_public_key` entries of the public key file as raw
+/// bytes; parsing them is left to the BLS backend.
+pub fn get_bls_public_keys(file_path: &Path) -> Result<HashMap<u32, Vec<u8>>, Box<dyn Error>> {
+    let mut public_keys = HashMap::new();
+    for (id, public_key) in read_key_entries(file_path, BLS_PUBLIC_KEYS_FILE_INDEX)? {
+        public_keys.insert(id, decode_base64(id, &public_key)?);
+    }
+    Ok(public_keys)
+}
+
+/// Reads the optional `bls_proof_of_possession` entries of the public key
+/// file as raw bytes, like `get_bls_public_keys`.
+pub fn get_bls_proofs_of_possession(file_path: &Path) -> Result<HashMap<u32, Vec<u8>>, Box<dyn Error>> {
+    let mut proofs = HashMap::new();
+    for (id, proof) in read_key_entries(file_path, BLS_PROOFS_OF_POSSESSION_FILE_INDEX)? {
+        proofs.insert(id, decode_base64(id, &proof)?);
+    }
+    Ok(proofs)
+}
+
+/// The `index` entry of every `[id]` table in the public key file.
+fn read_key_entries(file_path: &Path, index: &str) -> Result<Vec<(u32, String)>, Box<dyn Error>> {
+    let content = fs::read_to_string(file_path)
+        .map_err(|e| format!("failed to read public key file {}: {}", file_path.display(), e))?;
+    let data: Value = content.parse::<Value>()
+        .map_err(|e| format!("invalid public key file {}: {}", file_path.display(), e))?;
+    let data_table = data.as_table().ok_or("Expected TOML data to be a table")?;
+    let mut entries = Vec::new();
     for (node_id, node_info) in data_table {
-        if let Some(public_key_str) = node_info.get(PUBLIC_KEYS_FILE_INDEX).and_then(|v| v.as_str()) {
-            let id = node_id.parse::<u32>().expect("Failed to parse node id from public key file");
-            let public_key_bytes = general_purpose::STANDARD.decode(public_key_str).expect("Failed to decode base64 public key");
-            public_keys.insert(id, PublicKey::from_bytes(&public_key_bytes).unwrap());
+        if let Some(public_key_str) = node_info.get(index).and_then(|v| v.as_str()) {
+            let id = node_id.parse::<u32>()
+                .map_err(|_| format!("invalid node id {} in public key file {}", node_id, file_path.display()))?;
+            entries.push((id, public_key_str.to_string()));
         }
     }
-    public_keys
+    Ok(entries)
+}
+
+fn decode_base64(id: u32, encoded: &str) -> Result<Vec<u8>, String> {
+    general_purpose::STANDARD.decode(encoded).map_err(|e| format!("key entry of node {} is not valid base64: {}", id, e))
+}
+
+fn decode_public_key(id: u32, encoded: &str) -> Result<PublicKey, String> {
+    PublicKey::from_bytes(&decode_base64(id, encoded)?)
+        .map_err(|_| format!("public key of node {} is not an Ed25519 public key", id))
 }
 
-pub fn get_private_key(node_id: u32) -> Keypair {
-    let encoded_key = env::var(format!("{}{}", PRIVATE_KEY_ENV, node_id)).expect("Private key environment variable is not set");
-    println!("{}",encoded_key.to_string());
-    let key_data = general_purpose::STANDARD.decode(encoded_key).expect("Failed to decode base64 private key");
-    Keypair::from_bytes(&key_data).expect("Failed to parse private key")
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use rand::rngs::OsRng;
+    use crate::keys::generate_keypair;
+
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sparse-bullshark-initializer-{}-{}", std::process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    fn public_key() -> String {
+        general_purpose::STANDARD.encode(generate_keypair(&mut OsRng).public.as_bytes())
+    }
+
+    fn committee_entry(id: u32, port: u16, public_key: &str) -> String {
+        format!("[[node]]\nid = {}\nhost = \"127.0.0.1\"\nport = {}\npublic_key = \"{}\"\n", id, port, public_key)
+    }
+
+    /// A nodes CSV listing `ids`, each on its own port.
+    fn nodes_csv(name: &str, ids: &[u32]) -> PathBuf {
+        let lines: String = ids.iter().map(|id| format!("{},127.0.0.1,{}\n", id, 9000 + id)).collect();
+        temp_file(name, &format!("id,host,port\n{}", lines))
+    }
+
+    /// A public keys TOML with a key for each of `ids`.
+    fn public_keys_toml(name: &str, ids: &[u32]) -> PathBuf {
+        let tables: String = ids.iter().map(|id| format!("[{}]\npublic_key = \"{}\"\n", id, public_key())).collect();
+        temp_file(name, &tables)
+    }
+
+    fn error<T>(result: Result<T, Box<dyn Error>>) -> String {
+        match result {
+            Ok(_) => panic!("expected an error"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn committee(ids: &[u32]) -> Committee {
+        Committee {
+            nodes: ids.iter().map(|id| Node { id: *id, host: "127.0.0.1".to_string(), port: 9000 + *id as u16, stake: 1 }).collect(),
+            public_keys: ids.iter().map(|id| (*id, generate_keypair(&mut OsRng).public)).collect(),
+            bls_public_keys: HashMap::new(),
+            bls_proofs_of_possession: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn loads_a_committee_file() {
+        let keys = [public_key(), public_key()];
+        let content = format!("{}{}stake = 3\n", committee_entry(0, 9000, &keys[0]), committee_entry(1, 9001, &keys[1]));
+        let committee = load_committee(&temp_file("committee.toml", &content)).unwrap();
+        let nodes: Vec<(u32, u16, u64)> = committee.nodes.iter().map(|node| (node.id, node.port, node.stake)).collect();
+        assert_eq!(nodes, [(0, 9000, DEFAULT_STAKE), (1, 9001, 3)]);
+        assert_eq!(general_purpose::STANDARD.encode(committee.public_keys[&1].as_bytes()), keys[1]);
+        assert!(committee.bls_public_keys.is_empty());
+    }
+
+    #[test]
+    fn rejects_a_node_listed_twice_in_the_committee_file() {
+        let content = format!("{}{}", committee_entry(1, 9000, &public_key()), committee_entry(1, 9001, &public_key()));
+        let path = temp_file("twice.toml", &content);
+        assert_eq!(error(load_committee(&path)), format!("node 1 is listed twice in {}", path.display()));
+    }
+
+    #[test]
+    fn rejects_bad_keys_in_the_committee_file() {
+        let path = temp_file("not-base64.toml", &committee_entry(0, 9000, "not base64!"));
+        assert!(error(load_committee(&path)).starts_with("key entry of node 0 is not valid base64"));
+        let short = general_purpose::STANDARD.encode([7u8; 16]);
+        let path = temp_file("short-key.toml", &committee_entry(0, 9000, &short));
+        assert_eq!(error(load_committee(&path)), "public key of node 0 is not an Ed25519 public key");
+    }
+
+    #[test]
+    fn reports_the_committee_file_it_cannot_use() {
+        let path = temp_file("unknown-field.toml", &format!("{}weight = 2\n", committee_entry(0, 9000, &public_key())));
+        assert!(error(load_committee(&path)).starts_with(&format!("invalid committee file {}", path.display())));
+        let path = temp_file("reused-address.toml", &format!("{}{}", committee_entry(0, 9000, &public_key()), committee_entry(1, 9000, &public_key())));
+        assert_eq!(
+            error(load_committee(&path)),
+            format!("invalid committee file {}: node 1 reuses the address 127.0.0.1:9000", path.display())
+        );
+    }
+
+    #[test]
+    fn loads_a_legacy_pair() {
+        let committee = load_legacy_committee(&nodes_csv("nodes.csv", &[0, 1, 2]), &public_keys_toml("keys.toml", &[0, 1, 2])).unwrap();
+        assert_eq!(committee.nodes.len(), 3);
+        assert!(committee.nodes.iter().all(|node| node.stake == DEFAULT_STAKE && committee.public_keys.contains_key(&node.id)));
+    }
+
+    #[test]
+    fn the_legacy_files_must_list_the_same_nodes() {
+        let nodes = nodes_csv("same-nodes.csv", &[0, 1, 2]);
+        let too_few = public_keys_toml("too-few.toml", &[0, 1]);
+        assert_eq!(
+            error(load_legacy_committee(&nodes, &too_few)),
+            format!("{} and {} don't match: node 2 has no public key", nodes.display(), too_few.display())
+        );
+        let too_many = public_keys_toml("too-many.toml", &[0, 1, 2, 3]);
+        assert!(error(load_legacy_committee(&nodes, &too_many))
+            .ends_with("there is a key entry for node 3, which is not in the committee"));
+
+        let twice = nodes_csv("twice.csv", &[0, 1, 1]);
+        let keys = public_keys_toml("twice.toml", &[0, 1]);
+        assert!(error(load_legacy_committee(&twice, &keys)).ends_with("node 1 is listed twice"));
+    }
+
+    #[test]
+    fn rejects_a_bad_key_in_the_legacy_keys_file() {
+        let nodes = nodes_csv("bad-key.csv", &[0]);
+        let keys = temp_file("bad-key.toml", "[0]\npublic_key = \"AAAA\"\n");
+        assert_eq!(error(load_legacy_committee(&nodes, &keys)), "public key of node 0 is not an Ed25519 public key");
+        let keys = temp_file("bad-id.toml", &format!("[zero]\npublic_key = \"{}\"\n", public_key()));
+        assert_eq!(
+            error(load_legacy_committee(&nodes, &keys)),
+            format!("invalid node id zero in public key file {}", keys.display())
+        );
+    }
+
+    #[test]
+    fn checks_the_committee() {
+        check_committee(&committee(&[0, 1, 2])).unwrap();
+        assert_eq!(check_committee(&committee(&[])).unwrap_err(), "the committee has no nodes");
+
+        let mut zero_stake = committee(&[0, 1]);
+        zero_stake.nodes[1].stake = 0;
+        assert_eq!(check_committee(&zero_stake).unwrap_err(), "node 1 has no stake");
+
+        // BLS keys count as key entries too.
+        let mut stray_bls_key = committee(&[0, 1]);
+        stray_bls_key.bls_public_keys.insert(5, vec![1]);
+        assert_eq!(
+            check_committee(&stray_bls_key).unwrap_err(),
+            "there is a key entry for node 5, which is not in the committee"
+        );
+    }
 }
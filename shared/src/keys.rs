@@ -0,0 +1,273 @@
+//! Node private keys: generating them, writing them to key files and loading
+//! them back. Key material is never logged, and the buffers it passes
+//! through are zeroized once the key pair is built.
+
+use std::{env, fmt};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose, Engine as _};
+use ed25519_dalek::{Keypair, PublicKey, SecretKey, KEYPAIR_LENGTH, SECRET_KEY_LENGTH};
+use rand::{CryptoRng, RngCore};
+use zeroize::Zeroizing;
+
+pub const KEYS_DIRNAME: &str = "./shared/keys";
+/// Prefix of the environment variable holding node `id`'s key, `PRIVATE_KEY_<id>`.
+pub const PRIVATE_KEY_ENV: &str = "PRIVATE_KEY_";
+
+/// Why a private key could not be loaded.
+#[derive(Debug)]
+pub enum KeyError {
+    /// Neither a key file nor the environment variable was found.
+    Missing(u32),
+    Read(PathBuf, io::Error),
+    /// The key file can be read by other users.
+    Permissions(PathBuf, u32),
+    /// `source` doesn't hold a base64 encoded key pair.
+    Malformed { source: String, reason: String },
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Missing(id) => write!(
+                f, "no private key for node {}: set paths.private_key, {}{} or create {}",
+                id, PRIVATE_KEY_ENV, id, private_key_path(Path::new(KEYS_DIRNAME), *id).display()
+            ),
+            KeyError::Read(path, e) => write!(f, "failed to read private key {}: {}", path.display(), e),
+            KeyError::Permissions(path, mode) => write!(
+                f, "private key {} is accessible by other users (mode {:o}), restrict it with chmod 600",
+                path.display(), mode
+            ),
+            KeyError::Malformed { source, reason } => write!(f, "invalid private key in {}: {}", source, reason),
+        }
+    }
+}
+
+impl std::error::Error for KeyError {}
+
+/// A fresh Ed25519 key pair drawn from `rng`.
+pub fn generate_keypair<R: RngCore + CryptoRng>(rng: &mut R) -> Keypair {
+    let mut bytes = Zeroizing::new([0u8; SECRET_KEY_LENGTH]);
+    rng.fill_bytes(bytes.as_mut());
+    let secret = SecretKey::from_bytes(bytes.as_ref()).expect("Any 32 bytes are a valid secret key");
+    let public = PublicKey::from(&secret);
+    Keypair { secret, public }
+}
+
+/// The private key file of node `node_id` in `keys_dir`.
+pub fn private_key_path(keys_dir: &Path, node_id: u32) -> PathBuf {
+    keys_dir.join(format!("node-{}.key", node_id))
+}
+
+/// The base64 encoding of a key pair, as stored in private key files and
+/// `PRIVATE_KEY_<id>` variables.
+pub fn encode_private_key(keypair: &Keypair) -> Zeroizing<String> {
+    Zeroizing::new(general_purpose::STANDARD.encode(Zeroizing::new(keypair.to_bytes()).as_ref()))
+}
+
+/// Writes `keypair` to `path`, readable by its owner only. The directory is
+/// created, owner-only as well, if it doesn't exist. An existing file is
+/// only replaced with `overwrite`.
+pub fn write_private_key(path: &Path, keypair: &Keypair, overwrite: bool) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty() && !dir.exists()) {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        #[cfg(unix)]
+        std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+        builder.create(dir)?;
+    }
+    let mut options = OpenOptions::new();
+    options.write(true);
+    if overwrite {
+        options.create(true).truncate(true);
+    } else {
+        options.create_new(true);
+    }
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = options.open(path)?;
+    // The mode only applies to new files; tighten a replaced one as well.
+    #[cfg(unix)]
+    fs::set_permissions(path, std::os::unix::fs::PermissionsExt::from_mode(0o600))?;
+    writeln!(file, "{}", encode_private_key(keypair).as_str())?;
+    file.sync_all()
+}
+
+/// Loads the key pair of node `node_id`. It comes from `path` if given,
+/// otherwise from `PRIVATE_KEY_<id>`, otherwise from the node's file in
+/// `KEYS_DIRNAME`.
+pub fn load_private_key(node_id: u32, path: Option<&Path>) -> Result<Keypair, KeyError> {
+    if let Some(path) = path {
+        return read_private_key(path);
+    }
+    let var = format!("{}{}", PRIVATE_KEY_ENV, node_id);
+    if let Some(encoded) = env::var_os(&var) {
+        let encoded = Zeroizing::new(encoded.into_string().map_err(|_| KeyError::Malformed {
+            source: var.clone(),
+            reason: "not valid UTF-8".to_string(),
+        })?);
+        return decode_private_key(&encoded, &var);
+    }
+    let default_path = private_key_path(Path::new(KEYS_DIRNAME), node_id);
+    if default_path.exists() {
+        return read_private_key(&default_path);
+    }
+    Err(KeyError::Missing(node_id))
+}
+
+/// Reads a key file written by `write_private_key`, refusing it if other
+/// users may read it.
+pub fn read_private_key(path: &Path) -> Result<Keypair, KeyError> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path).map_err(|e| KeyError::Read(path.to_path_buf(), e))?.permissions().mode();
+        if mode & 0o077 != 0 {
+            return Err(KeyError::Permissions(path.to_path_buf(), mode & 0o777));
+        }
+    }
+    let encoded = Zeroizing::new(fs::read_to_string(path).map_err(|e| KeyError::Read(path.to_path_buf(), e))?);
+    decode_private_key(&encoded, &path.display().to_string())
+}
+
+fn decode_private_key(encoded: &str, source: &str) -> Result<Keypair, KeyError> {
+    let malformed = |reason: String| KeyError::Malformed { source: source.to_string(), reason };
+    // Only the error kind is reported: the messages may quote the input.
+    let bytes = Zeroizing::new(
+        general_purpose::STANDARD.decode(encoded.trim()).map_err(|_| malformed("not valid base64".to_string()))?
+    );
+    if bytes.len() != KEYPAIR_LENGTH {
+        return Err(malformed(format!("expected a {}-byte key pair, got {} bytes", KEYPAIR_LENGTH, bytes.len())));
+    }
+    let keypair = Keypair::from_bytes(&bytes).map_err(|_| malformed("not an Ed25519 key pair".to_string()))?;
+    if PublicKey::from(&keypair.secret) != keypair.public {
+        return Err(malformed("the public half doesn't match the secret key".to_string()));
+    }
+    Ok(keypair)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::OsRng;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("sparse-bullshark-keys-{}-{}", std::process::id(), name))
+    }
+
+    /// A written key file, and the key in it.
+    fn key_file(name: &str) -> (PathBuf, Keypair) {
+        let path = temp_path(name);
+        let keypair = generate_keypair(&mut OsRng);
+        write_private_key(&path, &keypair, true).unwrap();
+        (path, keypair)
+    }
+
+    fn malformed_reason(result: Result<Keypair, KeyError>) -> String {
+        match result {
+            Err(KeyError::Malformed { reason, .. }) => reason,
+            other => panic!("expected a malformed key, got {:?}", other.map(|keypair| keypair.public)),
+        }
+    }
+
+    #[test]
+    fn reads_back_a_written_key() {
+        let (path, keypair) = key_file("written");
+        assert_eq!(read_private_key(&path).unwrap().to_bytes(), keypair.to_bytes());
+        #[cfg(unix)]
+        assert_eq!(std::os::unix::fs::PermissionsExt::mode(&fs::metadata(&path).unwrap().permissions()) & 0o777, 0o600);
+
+        let other = generate_keypair(&mut OsRng);
+        let error = write_private_key(&path, &other, false).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::AlreadyExists);
+        write_private_key(&path, &other, true).unwrap();
+        assert_eq!(read_private_key(&path).unwrap().to_bytes(), other.to_bytes());
+    }
+
+    #[test]
+    fn a_given_path_comes_before_the_environment() {
+        // Each test touching PRIVATE_KEY_<id> uses its own id.
+        let id = 9_000_001;
+        let (path, keypair) = key_file("given");
+        env::set_var(format!("{}{}", PRIVATE_KEY_ENV, id), encode_private_key(&generate_keypair(&mut OsRng)).as_str());
+        let loaded = load_private_key(id, Some(&path));
+        env::remove_var(format!("{}{}", PRIVATE_KEY_ENV, id));
+        assert_eq!(loaded.unwrap().to_bytes(), keypair.to_bytes());
+    }
+
+    #[test]
+    fn the_environment_comes_before_the_default_file() {
+        let id = 9_000_002;
+        let var = format!("{}{}", PRIVATE_KEY_ENV, id);
+        let default_path = private_key_path(Path::new(KEYS_DIRNAME), id);
+        let in_file = generate_keypair(&mut OsRng);
+        let in_env = generate_keypair(&mut OsRng);
+        write_private_key(&default_path, &in_file, true).unwrap();
+
+        env::set_var(&var, encode_private_key(&in_env).as_str());
+        let from_env = load_private_key(id, None);
+        env::remove_var(&var);
+        let from_file = load_private_key(id, None);
+        fs::remove_file(&default_path).unwrap();
+        let missing = load_private_key(id, None);
+        // Only gets rid of the directories if this test created them.
+        let _ = fs::remove_dir(KEYS_DIRNAME).and_then(|_| fs::remove_dir(Path::new(KEYS_DIRNAME).parent().unwrap()));
+
+        assert_eq!(from_env.unwrap().to_bytes(), in_env.to_bytes());
+        assert_eq!(from_file.unwrap().to_bytes(), in_file.to_bytes());
+        assert!(matches!(missing, Err(KeyError::Missing(missing_id)) if missing_id == id));
+    }
+
+    #[test]
+    fn rejects_a_malformed_environment_variable() {
+        let id = 9_000_003;
+        let var = format!("{}{}", PRIVATE_KEY_ENV, id);
+        env::set_var(&var, "not base64!");
+        let loaded = load_private_key(id, None);
+        env::remove_var(&var);
+        match loaded {
+            Err(KeyError::Malformed { source, reason }) => {
+                assert_eq!(source, var);
+                assert_eq!(reason, "not valid base64");
+            }
+            other => panic!("expected a malformed key, got {:?}", other.map(|keypair| keypair.public)),
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn refuses_a_key_file_others_can_read() {
+        use std::os::unix::fs::PermissionsExt;
+        let (path, _) = key_file("readable");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        assert!(matches!(read_private_key(&path), Err(KeyError::Permissions(_, 0o640))));
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o400)).unwrap();
+        read_private_key(&path).unwrap();
+    }
+
+    #[test]
+    fn reports_a_missing_file() {
+        let path = temp_path("missing");
+        assert!(matches!(read_private_key(&path), Err(KeyError::Read(ref read, _)) if *read == path));
+    }
+
+    #[test]
+    fn rejects_malformed_keys() {
+        assert_eq!(malformed_reason(decode_private_key("%%%", "test")), "not valid base64");
+        let short = general_purpose::STANDARD.encode([1u8; 32]);
+        assert_eq!(
+            malformed_reason(decode_private_key(&short, "test")),
+            format!("expected a {}-byte key pair, got 32 bytes", KEYPAIR_LENGTH)
+        );
+        // The secret half of one key pair with the public half of another.
+        let (a, b) = (generate_keypair(&mut OsRng), generate_keypair(&mut OsRng));
+        let mut mixed = a.to_bytes();
+        mixed[SECRET_KEY_LENGTH..].copy_from_slice(b.public.as_bytes());
+        let mixed = general_purpose::STANDARD.encode(mixed);
+        assert_eq!(malformed_reason(decode_private_key(&mixed, "test")), "the public half doesn't match the secret key");
+        // Surrounding whitespace, such as the file's newline, is fine.
+        let encoded = format!("\n{}\n", encode_private_key(&a).as_str());
+        assert_eq!(decode_private_key(&encoded, "test").unwrap().to_bytes(), a.to_bytes());
+    }
+}
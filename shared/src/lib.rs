@@ -1,4 +1,6 @@
 pub mod initializer;
 pub mod transaction_generator;
 pub mod domain;
-pub mod vrf;
\ No newline at end of file
+pub mod vrf;
+pub mod hasher;
+pub mod keys;
\ No newline at end of file
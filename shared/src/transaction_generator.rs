@@ -1,7 +1,16 @@
 //use std::collections::VecDeque;
 use bincode::serialize;
+use rand::Rng;
 use crate::domain::transaction::{Transaction};
 
+/// Number of distinct keys the generated commands touch, small enough for
+/// puts, gets and deletes to hit the same keys.
+const KEY_SPACE: usize = 1024;
+
+fn key(index: usize) -> String {
+    // Fixed width, so every put has the same size.
+    format!("key-{:04}", index)
+}
 
 pub struct TransactionGenerator {
     padding: usize,
@@ -11,10 +20,11 @@ pub struct TransactionGenerator {
 
 impl TransactionGenerator {
 
+    /// `target_size` is the serialized size of a put; gets and deletes are smaller.
     pub fn new(target_size: usize, transactions_per_block: usize) -> Self {
         let mut padding_size = 0;
         loop {
-            let tx = Transaction::new(padding_size);
+            let tx = Transaction::Put { key: key(0), value: "X".repeat(padding_size) };
             let size = serialize(&tx).unwrap().len();
             if size >= target_size {
                 break
@@ -31,7 +41,16 @@ impl TransactionGenerator {
     }
      */
 
+    /// A block of random commands: 60% puts, 20% gets and 20% deletes.
     pub fn generate(&mut self) -> Vec<Transaction> {
-        (0..self.transactions_per_block).map(|_| Transaction::new(self.padding)).collect()
+        let mut rng = rand::thread_rng();
+        (0..self.transactions_per_block).map(|_| {
+            let key = key(rng.gen_range(0..KEY_SPACE));
+            match rng.gen_range(0..10) {
+                0..=5 => Transaction::Put { key, value: "X".repeat(self.padding) },
+                6 | 7 => Transaction::Get { key },
+                _ => Transaction::Delete { key },
+            }
+        }).collect()
     }
 }
@@ -2,7 +2,7 @@ use std::collections::HashSet;
 use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
 use rand::{SeedableRng};
 use rand::seq::SliceRandom;
-use sha2::{Sha256, Digest};
+use crate::hasher::{DefaultHasher, Hasher};
 
 
 pub fn vrf_prove(
@@ -18,11 +18,9 @@ pub fn vrf_prove(
     let seed_bytes = seed.as_bytes();
     let proof = private_key.sign(seed_bytes);
 
-    let mut hasher = Sha256::new();
-    hasher.update(proof.as_ref());
-    let hash_output = hasher.finalize();
+    let hash_output = DefaultHasher::digest(proof.as_ref());
 
-    let mut rng = rand::rngs::StdRng::from_seed(hash_output[0..32].try_into().unwrap());
+    let mut rng = rand::rngs::StdRng::from_seed(hash_output);
     possible_ids.shuffle(&mut rng);
 
     let mut sample_set: HashSet<u32> = possible_ids.into_iter().take(sample_size - 1).collect();
@@ -37,7 +35,7 @@ pub fn vrf_verify(
     sample_size: usize,
     s: u32,
     leader_id: u32,
-    sample_set: &Vec<u32>,
+    sample_set: &[u32],
     proof: &[u8],
 ) -> bool {
     let mut possible_ids: Vec<u32> = (0..s).collect();
@@ -49,11 +47,9 @@ pub fn vrf_verify(
         return false;
     }
 
-    let mut hasher = Sha256::new();
-    hasher.update(proof);
-    let hash_output = hasher.finalize();
+    let hash_output = DefaultHasher::digest(proof);
 
-    let mut rng = rand::rngs::StdRng::from_seed(hash_output[0..32].try_into().unwrap());
+    let mut rng = rand::rngs::StdRng::from_seed(hash_output);
     possible_ids.shuffle(&mut rng);
 
     let mut expected_sample_set: HashSet<u32> = possible_ids.into_iter().take(sample_size - 1).collect();
@@ -0,0 +1,185 @@
+//! The DAG at n = 100, against the previous one that scanned a
+//! `Vec<Vertex>` per round for (round, source) lookups and cloned every
+//! vertex it visited in `has_path`. Run with `cargo bench -p sparse_bullshark`.
+#![allow(dead_code)]
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+
+// The crate has no library target, so the DAG is compiled in from its sources.
+#[path = "../src/types"]
+mod types {
+    pub mod digest;
+    pub mod vertex;
+    pub mod equivocation;
+}
+#[path = "../src/crypto"]
+mod crypto {
+    pub mod hashing;
+}
+#[path = "../src/consensus"]
+mod consensus {
+    pub mod dag;
+}
+
+use consensus::dag::DAG;
+use crypto::hashing::Variant;
+use types::vertex::{NodeId, Vertex, VertexHash, GENESIS_HASH};
+
+const N: usize = 100;
+const ROUNDS: u64 = 20;
+/// Parents per vertex: a 2f+1 quorum of the previous round.
+const PARENTS: usize = 2 * ((N - 1) / 3) + 1;
+
+/// Every vertex of rounds 1..=ROUNDS, round by round. Vertex `i` links to the
+/// 2f+1 vertices of the previous round starting at source `i`.
+fn build_vertices() -> Vec<Vertex> {
+    let mut vertices = Vec::new();
+    let mut previous: Vec<VertexHash> = vec![GENESIS_HASH];
+    for round in 1..=ROUNDS {
+        let mut current = Vec::new();
+        for source in 0..N {
+            let edges = (0..PARENTS.min(previous.len())).map(|k| previous[(source + k) % previous.len()]).collect();
+            let mut vertex = Vertex {
+                hash: VertexHash::default(),
+                round,
+                source: source as NodeId,
+                block: vec![],
+                edges,
+                signed_round: vec![],
+                sample_proof: vec![],
+                signature: vec![],
+            };
+            vertex.hash = vertex.calculate_hash(Variant::Dense);
+            current.push(vertex.hash);
+            vertices.push(vertex);
+        }
+        previous = current;
+    }
+    vertices
+}
+
+/// The DAG as it was before it was indexed by (round, source).
+struct LinearDag {
+    rounds: HashMap<u64, Vec<Vertex>>,
+    vertices: HashMap<VertexHash, Vertex>,
+}
+
+impl LinearDag {
+    fn new() -> Self {
+        LinearDag { rounds: HashMap::new(), vertices: HashMap::new() }
+    }
+
+    fn insert(&mut self, vertex: Vertex) {
+        self.rounds.entry(vertex.round).or_default().push(vertex.clone());
+        self.vertices.insert(vertex.hash, self.rounds.get(&vertex.round).unwrap().last().unwrap().clone());
+    }
+
+    fn get(&self, round: u64, source: NodeId) -> Option<&Vertex> {
+        self.rounds.get(&round).and_then(|vertices| vertices.iter().find(|v| v.source == source))
+    }
+
+    fn has_path(&self, start_vertex: &Vertex, target_vertex: &Vertex) -> bool {
+        let mut queue = VecDeque::new();
+        let mut visited = HashSet::new();
+        queue.push_back(start_vertex.clone());
+        visited.insert(start_vertex.hash);
+        while let Some(current) = queue.pop_front() {
+            if current.hash == target_vertex.hash {
+                return true;
+            }
+            if current.round <= target_vertex.round {
+                continue;
+            }
+            for parent_hash in &current.edges {
+                if !visited.contains(parent_hash) {
+                    if let Some(parent_vertex) = self.vertices.get(parent_hash) {
+                        visited.insert(*parent_hash);
+                        queue.push_back(parent_vertex.clone());
+                    }
+                }
+            }
+        }
+        false
+    }
+}
+
+fn indexed_dag(vertices: &[Vertex]) -> DAG {
+    let mut dag = DAG::new();
+    for vertex in vertices {
+        dag.insert(vertex.clone()).unwrap();
+    }
+    dag
+}
+
+fn linear_dag(vertices: &[Vertex]) -> LinearDag {
+    let mut dag = LinearDag::new();
+    for vertex in vertices {
+        dag.insert(vertex.clone());
+    }
+    dag
+}
+
+/// The anchor of `round` for a leader rotating every two rounds.
+fn leader(round: u64) -> NodeId {
+    ((round / 2) % N as u64) as NodeId
+}
+
+fn bench_insert(c: &mut Criterion) {
+    let vertices = build_vertices();
+    let mut group = c.benchmark_group("insert 20 rounds");
+    group.bench_function("linear", |b| b.iter_batched(|| vertices.clone(), |vs| {
+        let mut dag = LinearDag::new();
+        for vertex in vs {
+            dag.insert(vertex);
+        }
+        dag
+    }, BatchSize::LargeInput));
+    group.bench_function("indexed", |b| b.iter_batched(|| vertices.clone(), |vs| {
+        let mut dag = DAG::new();
+        for vertex in vs {
+            dag.insert(vertex).unwrap();
+        }
+        dag
+    }, BatchSize::LargeInput));
+    group.finish();
+}
+
+fn bench_lookup(c: &mut Criterion) {
+    let vertices = build_vertices();
+    let linear = linear_dag(&vertices);
+    let indexed = indexed_dag(&vertices);
+    let lookups: Vec<(u64, NodeId)> = (1..=ROUNDS).flat_map(|r| (0..N as NodeId).map(move |s| (r, s))).collect();
+    let mut group = c.benchmark_group("(round, source) lookup of every vertex");
+    group.bench_function("linear", |b| b.iter(|| {
+        lookups.iter().filter(|(r, s)| linear.get(*r, *s).is_some()).count()
+    }));
+    group.bench_function("indexed", |b| b.iter(|| {
+        lookups.iter().filter(|(r, s)| indexed.get(*r, *s).is_some()).count()
+    }));
+    group.finish();
+}
+
+fn bench_has_path(c: &mut Criterion) {
+    let vertices = build_vertices();
+    let linear = linear_dag(&vertices);
+    let start = linear.get(ROUNDS, leader(ROUNDS)).unwrap().clone();
+    let targets: Vec<Vertex> = (2..ROUNDS).step_by(2).map(|r| linear.get(r, leader(r)).unwrap().clone()).collect();
+
+    let mut group = c.benchmark_group("has_path from the top anchor to every older anchor");
+    group.sample_size(20);
+    group.bench_function("linear", |b| b.iter(|| {
+        targets.iter().filter(|target| linear.has_path(&start, target)).count()
+    }));
+    group.bench_function("indexed", |b| b.iter_batched(|| indexed_dag(&vertices), |mut dag| {
+        targets.iter().filter(|target| dag.has_path(&start, target)).count()
+    }, BatchSize::LargeInput));
+    let mut memoised = indexed_dag(&vertices);
+    group.bench_function("indexed, memoised", |b| b.iter(|| {
+        targets.iter().filter(|target| memoised.has_path(&start, target)).count()
+    }));
+    group.finish();
+}
+
+criterion_group!(benches, bench_insert, bench_lookup, bench_has_path);
+criterion_main!(benches);
@@ -0,0 +1,72 @@
+//! Sample proof size and validation time of each proof scheme, with every
+//! committee member signing: the table in `crypto/bls.rs`. Run with
+//! `cargo bench -p sparse_bullshark --features bls --bench proofs`; without
+//! the feature only Ed25519 is measured.
+#![allow(dead_code)]
+
+use std::collections::HashMap;
+use criterion::{criterion_group, criterion_main, Criterion};
+use ed25519_dalek::{Keypair, PublicKey};
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use shared::keys::generate_keypair;
+
+// The crate has no library target, so the schemes are compiled in from their sources.
+#[path = "../src/types"]
+mod types {
+    pub mod digest;
+    pub mod vertex;
+    pub mod equivocation;
+}
+#[path = "../src/crypto"]
+mod crypto {
+    pub mod hashing;
+    pub mod multisig;
+    #[cfg(feature = "bls")]
+    pub mod bls;
+}
+
+use crypto::multisig::{Committee, Ed25519Proofs, ProofScheme};
+use types::vertex::NodeId;
+
+const SIZES: [usize; 4] = [4, 16, 64, 100];
+const EPOCH: u64 = 0;
+const ROUND: u64 = 1;
+
+fn keypairs(n: usize) -> Vec<Keypair> {
+    let mut rng = ChaCha20Rng::seed_from_u64(n as u64);
+    (0..n).map(|_| generate_keypair(&mut rng)).collect()
+}
+
+/// Aggregates a round signature of every member, prints the proof size and
+/// measures its validation.
+fn bench_scheme<S: ProofScheme>(c: &mut Criterion, name: &str, scheme: &S, keypairs: &[Keypair]) {
+    let n = keypairs.len();
+    let public_keys: HashMap<NodeId, PublicKey> = keypairs.iter().enumerate().map(|(id, kp)| (id as NodeId, kp.public)).collect();
+    let committee = Committee { public_keys: &public_keys, quorum: 2 * ((n - 1) / 3) + 1 };
+    let signatures: Vec<Vec<u8>> = keypairs.iter().map(|kp| scheme.sign_round(EPOCH, ROUND, kp)).collect();
+    let partials: Vec<(NodeId, &[u8])> = signatures.iter().enumerate().map(|(id, s)| (id as NodeId, s.as_slice())).collect();
+    let proof = scheme.aggregate(&partials);
+    assert_eq!(scheme.validate(EPOCH, ROUND, &proof, &committee).map(|signers| signers.len()), Ok(n));
+    println!("{} proof, n = {}: {} bytes", name, n, proof.len());
+
+    let mut group = c.benchmark_group(format!("validate, n = {}", n));
+    group.sample_size(20);
+    group.bench_function(name, |b| b.iter(|| scheme.validate(EPOCH, ROUND, &proof, &committee).unwrap()));
+    group.finish();
+}
+
+fn bench_proofs(c: &mut Criterion) {
+    for n in SIZES {
+        let keypairs = keypairs(n);
+        bench_scheme(c, "ed25519", &Ed25519Proofs, &keypairs);
+        #[cfg(feature = "bls")]
+        {
+            let public_keys = keypairs.iter().enumerate().map(|(id, kp)| (id as NodeId, crypto::bls::public_key(kp))).collect();
+            bench_scheme(c, "bls", &crypto::bls::BlsProofs::new(public_keys), &keypairs);
+        }
+    }
+}
+
+criterion_group!(benches, bench_proofs);
+criterion_main!(benches);
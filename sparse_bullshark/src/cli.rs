@@ -0,0 +1,318 @@
+//! Command line interface: running a node and the tools around it.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+use base64::{engine::general_purpose, Engine as _};
+use clap::{Args, Parser, Subcommand};
+use ed25519_dalek::Keypair;
+use rand::{rngs::OsRng, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+use sha2::{Digest, Sha256};
+use shared::initializer::{get_bls_proofs_of_possession, get_bls_public_keys, get_public_keys, load_committee, COMMITTEE_FILENAME, PUBLIC_KEYS_FILENAME};
+use shared::keys::{generate_keypair, private_key_path, write_private_key, KEYS_DIRNAME};
+use crate::config::{NodeConfig, Overrides, Protocol};
+use crate::storage::{wal::Wal, Record};
+
+#[derive(Parser, Debug)]
+#[command(name = "sparse_bullshark", version, about = "Sparse Bullshark DAG-BFT consensus node")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Run a node of the committee.
+    Run(RunArgs),
+    /// Generate a key pair per node: the public keys file and one private key file per node.
+    Keygen(KeygenArgs),
+    /// Write a committee file for nodes on consecutive ports, with the keys from keygen.
+    GenCommittee(GenCommitteeArgs),
+    /// Print the resolved configuration, the committee and optionally a write-ahead log summary.
+    Inspect(InspectArgs),
+}
+
+/// Where the configuration comes from, see `config`.
+#[derive(Args, Debug)]
+pub struct ConfigArgs {
+    /// TOML configuration file.
+    #[arg(long)]
+    pub config: Option<PathBuf>,
+    /// Override one configuration key, e.g. `--set timeouts.round_ms=500`. Repeatable.
+    #[arg(long = "set", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+    pub overrides: Vec<(String, String)>,
+}
+
+#[derive(Args, Debug)]
+pub struct RunArgs {
+    /// Id of this node in the committee.
+    #[arg(long)]
+    pub node_id: u32,
+    /// Size of a generated transaction, in bytes.
+    #[arg(long)]
+    pub tx_size: usize,
+    /// Transactions per block.
+    #[arg(long)]
+    pub tx_count: usize,
+    /// Protocol variant; overrides the configuration.
+    #[arg(long, value_enum)]
+    pub protocol: Option<Protocol>,
+    /// Neither sign nor verify peer messages. Benchmarking only.
+    #[arg(long)]
+    pub skip_signatures: bool,
+    #[command(flatten)]
+    pub config: ConfigArgs,
+}
+
+#[derive(Args, Debug)]
+pub struct KeygenArgs {
+    /// Number of nodes.
+    #[arg(long)]
+    pub size: u32,
+    /// Derive the keys from this seed instead of the OS random number
+    /// generator, so a test cluster can be recreated. Never for real keys.
+    #[arg(long)]
+    pub seed: Option<String>,
+    #[arg(long, default_value = PUBLIC_KEYS_FILENAME)]
+    pub public_keys: PathBuf,
+    /// Directory of the private key files, `node-<id>.key`.
+    #[arg(long, default_value = KEYS_DIRNAME)]
+    pub keys_dir: PathBuf,
+    /// Replace the output files if they exist.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct GenCommitteeArgs {
+    /// Number of nodes.
+    #[arg(long)]
+    pub size: u32,
+    /// Host of every node.
+    #[arg(long, default_value = "127.0.0.1")]
+    pub host: String,
+    /// Port of node 0; node i listens on base_port + i.
+    #[arg(long, default_value_t = 8081)]
+    pub base_port: u16,
+    /// Public keys written by keygen, one per node.
+    #[arg(long, default_value = PUBLIC_KEYS_FILENAME)]
+    pub public_keys: PathBuf,
+    #[arg(long, default_value = COMMITTEE_FILENAME)]
+    pub output: PathBuf,
+    /// Replace the output file if it exists.
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    #[command(flatten)]
+    pub config: ConfigArgs,
+    /// Write-ahead log to summarise.
+    #[arg(long)]
+    pub wal: Option<PathBuf>,
+}
+
+impl ConfigArgs {
+    /// The environment variable overrides followed by the `--set` ones.
+    pub fn overrides(&self) -> Overrides {
+        let mut overrides = NodeConfig::env_overrides();
+        overrides.extend(self.overrides.iter().cloned());
+        overrides
+    }
+}
+
+fn parse_key_value(setting: &str) -> Result<(String, String), String> {
+    let (key, value) = setting.split_once('=').ok_or_else(|| format!("expected KEY=VALUE, got {}", setting))?;
+    Ok((key.to_string(), value.to_string()))
+}
+
+pub fn keygen(args: &KeygenArgs) -> Result<(), Box<dyn Error>> {
+    if args.size == 0 {
+        return Err("the committee needs at least one node".into());
+    }
+    let key_paths: Vec<PathBuf> = (0..args.size).map(|id| private_key_path(&args.keys_dir, id)).collect();
+    if !args.force {
+        if let Some(path) = std::iter::once(&args.public_keys).chain(&key_paths).find(|path| path.exists()) {
+            return Err(format!("{} exists, pass --force to replace it", path.display()).into());
+        }
+    }
+    // Keys are drawn in id order, so a seed always gives node i the same key.
+    let mut seeded = args.seed.as_ref().map(|seed| ChaCha20Rng::from_seed(Sha256::digest(seed.as_bytes()).into()));
+    let keypairs: Vec<Keypair> = key_paths.iter()
+        .map(|_| match seeded.as_mut() {
+            Some(rng) => generate_keypair(rng),
+            None => generate_keypair(&mut OsRng),
+        })
+        .collect();
+    let mut public_keys = String::new();
+    for (id, keypair) in keypairs.iter().enumerate() {
+        public_keys.push_str(&format!("[{}]\n", id));
+        public_keys.push_str(&format!("public_key = \"{}\"\n", general_purpose::STANDARD.encode(keypair.public.as_bytes())));
+        #[cfg(feature = "bls")]
+        {
+            use crate::crypto::bls;
+            public_keys.push_str(&format!(
+                "bls_public_key = \"{}\"\n",
+                general_purpose::STANDARD.encode(bls::public_key(keypair).to_compressed())
+            ));
+            public_keys.push_str(&format!(
+                "bls_proof_of_possession = \"{}\"\n",
+                general_purpose::STANDARD.encode(bls::proof_of_possession(keypair).to_compressed())
+            ));
+        }
+        public_keys.push('\n');
+    }
+    // Public keys first: if they can't be written, no private key is left
+    // behind without its public half on record.
+    fs::write(&args.public_keys, public_keys)
+        .map_err(|e| format!("failed to write {}: {}", args.public_keys.display(), e))?;
+    for (path, keypair) in key_paths.iter().zip(&keypairs) {
+        write_private_key(path, keypair, args.force)
+            .map_err(|e| format!("failed to write {}: {}", path.display(), e))?;
+    }
+    println!("Wrote {} public keys to {}", args.size, args.public_keys.display());
+    println!("Wrote {} private keys to {}", args.size, args.keys_dir.display());
+    println!("Run gen-committee to combine the public keys with the node addresses.");
+    if args.keys_dir == Path::new(KEYS_DIRNAME) {
+        println!("Node i loads {} by default, or the file set as paths.private_key.", args.keys_dir.join("node-<i>.key").display());
+    } else {
+        // Nodes only look in KEYS_DIRNAME on their own.
+        println!(
+            "Nodes don't look in {} by default: set paths.private_key of node i to {}.",
+            args.keys_dir.display(), args.keys_dir.join("node-<i>.key").display()
+        );
+    }
+    if args.seed.is_some() {
+        println!("These keys are derived from a seed: use them for test clusters only.");
+    }
+    Ok(())
+}
+
+pub fn gen_committee(args: &GenCommitteeArgs) -> Result<(), Box<dyn Error>> {
+    if args.size == 0 {
+        return Err("the committee needs at least one node".into());
+    }
+    if u32::from(args.base_port) + args.size - 1 > u32::from(u16::MAX) {
+        return Err(format!("{} nodes don't fit on ports from {}", args.size, args.base_port).into());
+    }
+    if args.output.exists() && !args.force {
+        return Err(format!("{} exists, pass --force to replace it", args.output.display()).into());
+    }
+    let public_keys = get_public_keys(&args.public_keys)?;
+    let bls_public_keys = get_bls_public_keys(&args.public_keys)?;
+    let bls_proofs_of_possession = get_bls_proofs_of_possession(&args.public_keys)?;
+    if public_keys.len() as u64 != u64::from(args.size) {
+        return Err(format!(
+            "{} has {} public keys for {} nodes, run keygen --size {}",
+            args.public_keys.display(), public_keys.len(), args.size, args.size
+        ).into());
+    }
+    let mut committee = String::from("# Generated by gen-committee. Every node may also set a stake, 1 by default.\n");
+    for id in 0..args.size {
+        let public_key = public_keys.get(&id)
+            .ok_or_else(|| format!("{} has no public key for node {}", args.public_keys.display(), id))?;
+        committee.push_str("\n[[node]]\n");
+        committee.push_str(&format!("id = {}\n", id));
+        committee.push_str(&format!("host = \"{}\"\n", args.host));
+        committee.push_str(&format!("port = {}\n", u32::from(args.base_port) + id));
+        committee.push_str(&format!("public_key = \"{}\"\n", general_purpose::STANDARD.encode(public_key.as_bytes())));
+        if let Some(bls_public_key) = bls_public_keys.get(&id) {
+            committee.push_str(&format!("bls_public_key = \"{}\"\n", general_purpose::STANDARD.encode(bls_public_key)));
+        }
+        if let Some(proof) = bls_proofs_of_possession.get(&id) {
+            committee.push_str(&format!("bls_proof_of_possession = \"{}\"\n", general_purpose::STANDARD.encode(proof)));
+        }
+    }
+    fs::write(&args.output, committee)?;
+    // Catches a host that makes the file unreadable, among others.
+    load_committee(&args.output)?;
+    println!("Wrote {} nodes to {}", args.size, args.output.display());
+    Ok(())
+}
+
+pub fn inspect(args: &InspectArgs) -> Result<(), Box<dyn Error>> {
+    let config = NodeConfig::load(args.config.config.as_deref(), &args.config.overrides())?;
+    config.validate()?;
+    println!("{:#?}", config);
+
+    let source = config.paths.committee_source()?;
+    let committee = source.load()?;
+    config.validate_committee(committee.nodes.len())?;
+    let nodes = &committee.nodes;
+    println!("\nCommittee of {} (f = {}) from {:?}:", nodes.len(), nodes.len().saturating_sub(1) / 3, source);
+    for node in nodes {
+        // Loading checked that every node has a key.
+        let key = general_purpose::STANDARD.encode(committee.public_keys[&node.id].as_bytes());
+        println!("  {:>3}  {}:{}  stake {}  {}", node.id, node.host, node.port, node.stake, key);
+    }
+
+    if let Some(path) = &args.wal {
+        let (records, torn) = Wal::read(path)?;
+        let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+        let mut rounds = (u64::MAX, 0);
+        let mut last_ordered = None;
+        for record in &records {
+            let kind = match record {
+                Record::Vertex(_) => "vertex",
+                Record::Proposal(_) => "proposal",
+                Record::RbcEcho { .. } => "rbc echo",
+                Record::RbcReady { .. } => "rbc ready",
+                Record::RbcDelivered { .. } => "rbc delivered",
+                Record::LastOrderedRound { round } => {
+                    last_ordered = last_ordered.max(Some(*round));
+                    "last ordered round"
+                }
+                Record::Snapshot { round, .. } => {
+                    last_ordered = last_ordered.max(Some(*round));
+                    "snapshot"
+                }
+            };
+            *counts.entry(kind).or_default() += 1;
+            rounds = (rounds.0.min(record.round()), rounds.1.max(record.round()));
+        }
+        println!("\n{}: {} records, {} torn bytes at the end", path.display(), records.len(), torn);
+        if !records.is_empty() {
+            println!("  rounds {} to {}, last ordered round {:?}", rounds.0, rounds.1, last_ordered);
+        }
+        for (kind, count) in counts {
+            println!("  {:>8}  {}", count, kind);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("sparse-bullshark-cli-{}-{}", std::process::id(), name))
+    }
+
+    fn keygen_args(name: &str, public_keys: PathBuf) -> KeygenArgs {
+        KeygenArgs { size: 3, seed: Some("test".to_string()), public_keys, keys_dir: temp_path(name), force: false }
+    }
+
+    #[test]
+    fn keygen_writes_matching_key_files() {
+        let args = keygen_args("keys", temp_path("public_keys.toml"));
+        keygen(&args).unwrap();
+        let public_keys = get_public_keys(&args.public_keys).unwrap();
+        for id in 0..args.size {
+            let keypair = shared::keys::read_private_key(&private_key_path(&args.keys_dir, id)).unwrap();
+            assert_eq!(public_keys[&id], keypair.public);
+        }
+        // Nothing is replaced without --force.
+        assert!(keygen(&args).is_err());
+    }
+
+    #[test]
+    fn keygen_writes_no_private_key_if_the_public_keys_fail() {
+        let args = keygen_args("orphans", temp_path("no-such-dir").join("public_keys.toml"));
+        assert!(keygen(&args).unwrap_err().to_string().starts_with(&format!("failed to write {}", args.public_keys.display())));
+        assert!(!args.keys_dir.exists());
+    }
+}
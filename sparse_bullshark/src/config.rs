@@ -0,0 +1,516 @@
+//! Node configuration: a TOML file (see `shared/node.toml`) with every key
+//! optional, overridden by the legacy environment variables and then by
+//! `--set key=value` on the command line (see `cli`).
+
+use std::{env, fmt, fs, io};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+use clap::ValueEnum;
+use serde::Deserialize;
+use toml::{Table, Value};
+use tokio::time::Duration;
+use shared::domain::committee::Committee;
+use shared::initializer::{load_committee, load_legacy_committee, COMMITTEE_FILENAME, NODES_FILENAME, PUBLIC_KEYS_FILENAME};
+
+/// Environment variables still honoured, and the key each one sets.
+const ENV_OVERRIDES: &[(&str, &str)] = &[
+    ("PROTOCOL", "protocol"),
+    ("ROUND_TIMEOUT_MS", "timeouts.round_ms"),
+    ("GC_DEPTH", "gc_depth"),
+    ("STATE_MACHINE", "state_machine"),
+    ("DATA_DIR", "paths.data_dir"),
+];
+
+/// `(key, value)` pairs applied on top of the file, in order.
+pub type Overrides = Vec<(String, String)>;
+
+/// Why the configuration could not be loaded.
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(String),
+    Override(String),
+    Invalid(String),
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Read(path, e) => write!(f, "failed to read {}: {}", path.display(), e),
+            ConfigError::Parse(reason) => write!(f, "invalid configuration: {}", reason),
+            ConfigError::Override(reason) => write!(f, "invalid override: {}", reason),
+            ConfigError::Invalid(reason) => write!(f, "invalid configuration: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+#[derive(Deserialize, ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    Sparse,
+    #[serde(alias = "standard")]
+    #[value(alias = "standard")]
+    Dense,
+}
+
+/// The state machine ordered blocks are executed on.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Application {
+    Noop,
+    Counter,
+    Kv,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub protocol: Protocol,
+    /// Parents sampled per vertex by Sparse Bullshark (`d`).
+    pub sparsity: usize,
+    /// Round signatures are bound to it, so proofs from another run of the
+    /// committee are rejected.
+    pub epoch: u64,
+    pub state_machine: Application,
+    /// How many rounds below the last ordered anchor are kept.
+    pub gc_depth: u64,
+    pub timeouts: Timeouts,
+    pub limits: Limits,
+    pub paths: Paths,
+}
+
+impl Default for NodeConfig {
+    fn default() -> Self {
+        NodeConfig {
+            protocol: Protocol::Sparse,
+            sparsity: 2,
+            epoch: 0,
+            state_machine: Application::Noop,
+            gc_depth: 50,
+            timeouts: Timeouts::default(),
+            limits: Limits::default(),
+            paths: Paths::default(),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// How long to wait for the leader's anchor (even rounds) or for its votes
+    /// (odd rounds) before moving on with just a quorum of vertices.
+    pub round_ms: u64,
+    /// How long a missing vertex may stay missing before we ask (another) peer for it.
+    pub sync_retry_ms: u64,
+    pub connect_ms: u64,
+    /// How often dropped connections are dialed again.
+    pub reconnect_ms: u64,
+    /// Time given to the other nodes to bind their sockets before dialing them.
+    pub startup_delay_ms: u64,
+    /// How long the node runs before printing its statistics and exiting.
+    pub run_secs: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Timeouts {
+            round_ms: 1000,
+            sync_retry_ms: 500,
+            connect_ms: 1000,
+            reconnect_ms: 1000,
+            startup_delay_ms: 2000,
+            run_secs: 120,
+        }
+    }
+}
+
+impl Timeouts {
+    pub fn round(&self) -> Duration {
+        Duration::from_millis(self.round_ms)
+    }
+
+    pub fn sync_retry(&self) -> Duration {
+        Duration::from_millis(self.sync_retry_ms)
+    }
+
+    pub fn connect(&self) -> Duration {
+        Duration::from_millis(self.connect_ms)
+    }
+
+    pub fn reconnect(&self) -> Duration {
+        Duration::from_millis(self.reconnect_ms)
+    }
+
+    pub fn startup_delay(&self) -> Duration {
+        Duration::from_millis(self.startup_delay_ms)
+    }
+
+    pub fn run(&self) -> Duration {
+        Duration::from_secs(self.run_secs)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Limits {
+    /// Largest message accepted from a peer; the connection is dropped on a larger one.
+    pub max_frame_bytes: usize,
+    /// Capacity of the inbound and outbound message queues.
+    pub channel_size: usize,
+    /// How many vertices a single sender may have waiting for parents. An honest
+    /// sender produces one vertex per round, so this is how far ahead of us it
+    /// can get before its vertices are dropped (and later fetched again by sync).
+    pub max_pending_per_sender: usize,
+    /// Upper bound on the hashes in one sync request and the vertices in one response.
+    pub max_sync_batch: usize,
+    /// How many committed sub-DAGs may wait for the application before the
+    /// node stops handling messages until it catches up.
+    pub commit_channel_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_frame_bytes: 10 * 1024 * 1024,
+            channel_size: 1024,
+            max_pending_per_sender: 1024,
+            max_sync_batch: 64,
+            commit_channel_size: 1024,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Paths {
+    /// The committee file, see `CommitteeSource` for the default.
+    pub committee: Option<PathBuf>,
+    /// The legacy pair replaced by `committee`.
+    pub nodes: Option<PathBuf>,
+    pub public_keys: Option<PathBuf>,
+    /// This node's private key file; see `shared::keys::load_private_key`
+    /// for where the key comes from without it.
+    pub private_key: Option<PathBuf>,
+    /// Where the write-ahead log goes. Nothing is persisted without it.
+    pub data_dir: Option<PathBuf>,
+}
+
+/// Where the committee is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommitteeSource {
+    File(PathBuf),
+    /// A nodes CSV and a public keys TOML.
+    Legacy { nodes: PathBuf, public_keys: PathBuf },
+}
+
+impl CommitteeSource {
+    pub fn load(&self) -> Result<Committee, Box<dyn Error>> {
+        match self {
+            CommitteeSource::File(path) => load_committee(path),
+            CommitteeSource::Legacy { nodes, public_keys } => load_legacy_committee(nodes, public_keys),
+        }
+    }
+}
+
+impl Paths {
+    /// `paths.committee` if set, else the legacy pair if either half is set.
+    /// With neither, the default committee file, or the default legacy pair
+    /// if only that exists.
+    pub fn committee_source(&self) -> Result<CommitteeSource, ConfigError> {
+        let legacy = |nodes: Option<&PathBuf>, public_keys: Option<&PathBuf>| CommitteeSource::Legacy {
+            nodes: nodes.cloned().unwrap_or_else(|| PathBuf::from(NODES_FILENAME)),
+            public_keys: public_keys.cloned().unwrap_or_else(|| PathBuf::from(PUBLIC_KEYS_FILENAME)),
+        };
+        match (&self.committee, self.nodes.is_some() || self.public_keys.is_some()) {
+            (Some(_), true) => Err(ConfigError::Invalid(
+                "paths.committee replaces paths.nodes and paths.public_keys, set only one of them".to_string()
+            )),
+            (Some(committee), false) => Ok(CommitteeSource::File(committee.clone())),
+            (None, true) => Ok(legacy(self.nodes.as_ref(), self.public_keys.as_ref())),
+            (None, false) => {
+                let default = PathBuf::from(COMMITTEE_FILENAME);
+                if !default.exists() && Path::new(NODES_FILENAME).exists() {
+                    Ok(legacy(None, None))
+                } else {
+                    Ok(CommitteeSource::File(default))
+                }
+            }
+        }
+    }
+}
+
+impl NodeConfig {
+    /// Reads the file at `path`, if any, and applies `overrides` in order.
+    /// Keys are dotted paths into the file, e.g. `timeouts.round_ms`.
+    pub fn load(path: Option<&Path>, overrides: &[(String, String)]) -> Result<Self, ConfigError> {
+        let mut table = Table::new();
+        if let Some(path) = path {
+            let text = fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+            // Checked on its own first, so errors point into the file itself.
+            toml::from_str::<NodeConfig>(&text)
+                .map_err(|e| ConfigError::Parse(format!("{}: {}", path.display(), e)))?;
+            table = text.parse().expect("A valid configuration is a valid table");
+        }
+        for (key, value) in overrides {
+            set(&mut table, key, value)?;
+        }
+        // Going through the text, rather than deserializing the table, makes
+        // errors quote the offending key.
+        toml::from_str(&table.to_string()).map_err(|e| ConfigError::Override(e.to_string()))
+    }
+
+    /// The overrides given by environment variables.
+    pub fn env_overrides() -> Overrides {
+        ENV_OVERRIDES.iter()
+            .filter_map(|(var, key)| env::var(var).ok().map(|value| (key.to_string(), value)))
+            .collect()
+    }
+
+    /// Checks the values that don't depend on the committee.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        let positive = [
+            ("gc_depth", self.gc_depth),
+            ("timeouts.round_ms", self.timeouts.round_ms),
+            ("timeouts.sync_retry_ms", self.timeouts.sync_retry_ms),
+            ("timeouts.connect_ms", self.timeouts.connect_ms),
+            ("timeouts.reconnect_ms", self.timeouts.reconnect_ms),
+            ("timeouts.run_secs", self.timeouts.run_secs),
+            ("limits.max_frame_bytes", self.limits.max_frame_bytes as u64),
+            ("limits.channel_size", self.limits.channel_size as u64),
+            ("limits.max_pending_per_sender", self.limits.max_pending_per_sender as u64),
+            ("limits.max_sync_batch", self.limits.max_sync_batch as u64),
+            ("limits.commit_channel_size", self.limits.commit_channel_size as u64),
+        ];
+        if let Some((key, _)) = positive.iter().find(|(_, value)| *value == 0) {
+            return Err(ConfigError::Invalid(format!("{} must be greater than 0", key)));
+        }
+        // Frame lengths go over the wire as a u32.
+        if self.limits.max_frame_bytes > u32::MAX as usize {
+            return Err(ConfigError::Invalid(format!("limits.max_frame_bytes must be at most {}", u32::MAX)));
+        }
+        let mut files = match self.paths.committee_source()? {
+            CommitteeSource::File(committee) => vec![("paths.committee", committee)],
+            CommitteeSource::Legacy { nodes, public_keys } => vec![("paths.nodes", nodes), ("paths.public_keys", public_keys)],
+        };
+        files.extend(self.paths.private_key.clone().map(|path| ("paths.private_key", path)));
+        for (key, path) in &files {
+            if !path.is_file() {
+                return Err(ConfigError::Invalid(format!("{} {} is not a file", key, path.display())));
+            }
+        }
+        if let Some(data_dir) = self.paths.data_dir.as_ref().filter(|dir| dir.exists() && !dir.is_dir()) {
+            return Err(ConfigError::Invalid(format!("paths.data_dir {} is not a directory", data_dir.display())));
+        }
+        Ok(())
+    }
+
+    /// Checks the values that depend on the size of the committee.
+    pub fn validate_committee(&self, committee_size: usize) -> Result<(), ConfigError> {
+        if self.protocol == Protocol::Sparse && !(1..=committee_size).contains(&self.sparsity) {
+            return Err(ConfigError::Invalid(format!(
+                "sparsity must be between 1 and the committee size {}, got {}", committee_size, self.sparsity
+            )));
+        }
+        Ok(())
+    }
+}
+
+/// Sets the dotted `key` of `table` to `raw`, read as a TOML value if it is
+/// one (`4`, `true`, `"x"`) and as a plain string otherwise.
+fn set(table: &mut Table, key: &str, raw: &str) -> Result<(), ConfigError> {
+    let value = format!("value = {}", raw).parse::<Table>().ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| Value::String(raw.to_string()));
+    let mut parts: Vec<&str> = key.split('.').collect();
+    let last = parts.pop().filter(|last| !last.is_empty())
+        .ok_or_else(|| ConfigError::Override(format!("empty key in {}", key)))?;
+    let mut current = table;
+    for part in parts {
+        current = current.entry(part)
+            .or_insert_with(|| Value::Table(Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| ConfigError::Override(format!("{} is not a section in {}", part, key)))?;
+    }
+    current.insert(last.to_string(), value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("sparse-bullshark-config-{}-{}", std::process::id(), name))
+    }
+
+    fn overrides(pairs: &[(&str, &str)]) -> Overrides {
+        pairs.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect()
+    }
+
+    /// The defaults, loaded through an existing committee file.
+    fn valid(name: &str) -> NodeConfig {
+        let committee = temp_path(name);
+        fs::write(&committee, "").unwrap();
+        NodeConfig::load(None, &overrides(&[("paths.committee", committee.to_str().unwrap())])).unwrap()
+    }
+
+    fn invalid(config: &NodeConfig) -> String {
+        config.validate().unwrap_err().to_string()
+    }
+
+    #[test]
+    fn defaults_without_a_file() {
+        let config = NodeConfig::load(None, &[]).unwrap();
+        assert_eq!(config.protocol, Protocol::Sparse);
+        assert_eq!(config.state_machine, Application::Noop);
+        assert_eq!((config.sparsity, config.epoch, config.gc_depth), (2, 0, 50));
+        assert_eq!(config.timeouts.round_ms, Timeouts::default().round_ms);
+        assert_eq!(config.limits.max_sync_batch, Limits::default().max_sync_batch);
+        assert!(config.paths.committee.is_none() && config.paths.data_dir.is_none());
+        valid("defaults").validate().unwrap();
+    }
+
+    #[test]
+    fn overrides_apply_on_top_of_the_file_in_order() {
+        let path = temp_path("file.toml");
+        fs::write(&path, "protocol = \"dense\"\ngc_depth = 7\n[timeouts]\nround_ms = 300\nsync_retry_ms = 20\n").unwrap();
+        let config = NodeConfig::load(Some(&path), &overrides(&[
+            ("timeouts.round_ms", "400"),
+            ("state_machine", "kv"),
+            ("paths.data_dir", "/tmp/wal"),
+            ("gc_depth", "8"),
+            ("gc_depth", "9"),
+        ])).unwrap();
+        assert_eq!(config.protocol, Protocol::Dense);
+        assert_eq!(config.gc_depth, 9);
+        assert_eq!(config.timeouts.round_ms, 400);
+        // Keys of the same section not overridden stay as in the file.
+        assert_eq!(config.timeouts.sync_retry_ms, 20);
+        assert_eq!(config.timeouts.connect_ms, Timeouts::default().connect_ms);
+        assert_eq!(config.state_machine, Application::Kv);
+        assert_eq!(config.paths.data_dir, Some(PathBuf::from("/tmp/wal")));
+    }
+
+    #[test]
+    fn set_types_values_like_toml() {
+        let mut table = Table::new();
+        set(&mut table, "a.flag", "true").unwrap();
+        set(&mut table, "a.b.count", "4").unwrap();
+        set(&mut table, "quoted", "\"4\"").unwrap();
+        set(&mut table, "plain", "kv").unwrap();
+        assert_eq!(table["a"]["flag"], Value::Boolean(true));
+        assert_eq!(table["a"]["b"]["count"], Value::Integer(4));
+        assert_eq!(table["quoted"], Value::String("4".to_string()));
+        assert_eq!(table["plain"], Value::String("kv".to_string()));
+    }
+
+    #[test]
+    fn rejects_bad_overrides() {
+        let error = |pairs: &[(&str, &str)]| NodeConfig::load(None, &overrides(pairs)).unwrap_err().to_string();
+        assert!(error(&[("gc_depth", "many")]).contains("gc_depth"));
+        assert!(error(&[("no_such_key", "1")]).contains("no_such_key"));
+        assert_eq!(error(&[("timeouts.", "1")]), "invalid override: empty key in timeouts.");
+        assert_eq!(error(&[("gc_depth", "1"), ("gc_depth.x", "1")]), "invalid override: gc_depth is not a section in gc_depth.x");
+    }
+
+    #[test]
+    fn reports_the_file_it_cannot_read_or_parse() {
+        let missing = temp_path("missing.toml");
+        let error = NodeConfig::load(Some(&missing), &[]).unwrap_err();
+        assert!(matches!(error, ConfigError::Read(ref path, _) if *path == missing));
+
+        let path = temp_path("unknown.toml");
+        fs::write(&path, "gc_dept = 5\n").unwrap();
+        let error = NodeConfig::load(Some(&path), &[]).unwrap_err().to_string();
+        assert!(error.starts_with(&format!("invalid configuration: {}", path.display())), "{}", error);
+        assert!(error.contains("gc_dept"), "{}", error);
+    }
+
+    #[test]
+    fn reads_the_legacy_environment_variables() {
+        // No other test reads these.
+        env::set_var("GC_DEPTH", "12");
+        env::set_var("ROUND_TIMEOUT_MS", "250");
+        env::set_var("PROTOCOL", "standard");
+        let overrides = NodeConfig::env_overrides();
+        for var in ["GC_DEPTH", "ROUND_TIMEOUT_MS", "PROTOCOL"] {
+            env::remove_var(var);
+        }
+        assert!(overrides.contains(&("gc_depth".to_string(), "12".to_string())));
+        assert!(overrides.contains(&("timeouts.round_ms".to_string(), "250".to_string())));
+        let config = NodeConfig::load(None, &overrides).unwrap();
+        assert_eq!((config.gc_depth, config.timeouts.round_ms, config.protocol), (12, 250, Protocol::Dense));
+    }
+
+    #[test]
+    fn every_count_and_timeout_must_be_positive() {
+        let keys = [
+            "gc_depth", "timeouts.round_ms", "timeouts.sync_retry_ms", "timeouts.connect_ms", "timeouts.reconnect_ms",
+            "timeouts.run_secs", "limits.max_frame_bytes", "limits.channel_size", "limits.max_pending_per_sender",
+            "limits.max_sync_batch", "limits.commit_channel_size",
+        ];
+        for key in keys {
+            let config = NodeConfig::load(None, &overrides(&[(key, "0")])).unwrap();
+            assert_eq!(invalid(&config), format!("invalid configuration: {} must be greater than 0", key));
+        }
+    }
+
+    #[test]
+    fn frames_must_fit_a_u32_length() {
+        let mut config = valid("frames");
+        config.limits.max_frame_bytes = u32::MAX as usize + 1;
+        assert_eq!(invalid(&config), format!("invalid configuration: limits.max_frame_bytes must be at most {}", u32::MAX));
+    }
+
+    #[test]
+    fn the_committee_file_and_the_legacy_pair_exclude_each_other() {
+        let mut config = valid("both");
+        config.paths.nodes = Some(PathBuf::from("nodes.csv"));
+        assert_eq!(
+            invalid(&config),
+            "invalid configuration: paths.committee replaces paths.nodes and paths.public_keys, set only one of them"
+        );
+    }
+
+    #[test]
+    fn the_files_must_exist() {
+        let missing = temp_path("missing");
+        let mut config = valid("files");
+        config.paths.private_key = Some(missing.clone());
+        assert_eq!(invalid(&config), format!("invalid configuration: paths.private_key {} is not a file", missing.display()));
+
+        config.paths.private_key = None;
+        config.paths.committee = None;
+        config.paths.nodes = Some(missing.clone());
+        assert_eq!(invalid(&config), format!("invalid configuration: paths.nodes {} is not a file", missing.display()));
+    }
+
+    #[test]
+    fn the_data_dir_must_be_a_directory() {
+        let mut config = valid("data-dir");
+        config.paths.data_dir = config.paths.committee.clone();
+        let file = config.paths.committee.clone().unwrap();
+        assert_eq!(invalid(&config), format!("invalid configuration: paths.data_dir {} is not a directory", file.display()));
+        // One that doesn't exist yet is fine, the log creates it.
+        config.paths.data_dir = Some(temp_path("not-yet"));
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn sparsity_must_fit_the_committee() {
+        let mut config = valid("sparsity");
+        config.validate_committee(4).unwrap();
+        config.sparsity = 5;
+        assert_eq!(
+            config.validate_committee(4).unwrap_err().to_string(),
+            "invalid configuration: sparsity must be between 1 and the committee size 4, got 5"
+        );
+        config.sparsity = 0;
+        assert!(config.validate_committee(4).is_err());
+        // Dense Bullshark doesn't sample.
+        config.protocol = Protocol::Dense;
+        config.validate_committee(4).unwrap();
+    }
+}
@@ -1,70 +1,301 @@
-use crate::types::vertex::{Vertex};
-use std::{collections::{HashMap, HashSet, VecDeque}};
+use crate::types::{equivocation::Equivocation, vertex::Vertex};
+use std::{collections::{HashMap, HashSet, VecDeque}, sync::Arc};
 use crate::types::vertex::{NodeId,VertexHash};
 
+/// The vertices of one round, by source.
+pub type Round = HashMap<NodeId, Arc<Vertex>>;
+
+/// The DAG of delivered vertices. Each vertex is stored once and shared
+/// between the two indexes, by hash and by (round, source).
+#[allow(clippy::upper_case_acronyms)]
 pub struct DAG {
-    pub rounds: HashMap<u64, Vec<Vertex>>,
-    pub vertices: HashMap<VertexHash, Vertex>,
+    pub rounds: HashMap<u64, Round>,
+    pub vertices: HashMap<VertexHash, Arc<Vertex>>,
+    /// Lowest round still held. Everything below it was garbage collected.
+    pub gc_round: u64,
+    /// Answers of `has_path`, by target then start. A vertex is only inserted
+    /// with all its parents, so its history never changes afterwards and an
+    /// answer stays valid until one of the two is pruned.
+    reachable: HashMap<VertexHash, HashMap<VertexHash, bool>>,
 }
 impl DAG {
     pub fn new() -> Self {
-        Self{ 
+        Self{
             rounds : HashMap::new(),
-            vertices : HashMap::new(), 
+            vertices : HashMap::new(),
+            gc_round: 0,
+            reachable: HashMap::new(),
+        }
+    }
+
+    /// Inserts `vertex` unless its source already has a different vertex in
+    /// that round; the conflicting pair is returned instead and the DAG is
+    /// left unchanged. Returns whether the vertex was inserted: inserting it
+    /// twice, or into a garbage collected round, changes nothing.
+    pub fn insert(&mut self, vertex: Vertex) -> Result<bool, Box<Equivocation>> {
+        if self.is_pruned(vertex.round) {
+            return Ok(false);
+        }
+        let round = self.rounds.entry(vertex.round).or_default();
+        if let Some(existing) = round.get(&vertex.source) {
+            if existing.hash == vertex.hash {
+                return Ok(false);
+            }
+            return Err(Box::new(Equivocation {
+                source: vertex.source,
+                round: vertex.round,
+                first: existing.as_ref().clone(),
+                second: vertex,
+            }));
+        }
+        let vertex = Arc::new(vertex);
+        round.insert(vertex.source, vertex.clone());
+        self.vertices.insert(vertex.hash, vertex);
+        Ok(true)
+    }
+
+    /// Drops every round below `round` and returns the removed vertices.
+    pub fn prune_below(&mut self, round: u64) -> Vec<Arc<Vertex>> {
+        let mut removed = Vec::new();
+        for r in self.gc_round..round {
+            for vertex in self.rounds.remove(&r).unwrap_or_default().into_values() {
+                self.vertices.remove(&vertex.hash);
+                removed.push(vertex);
+            }
         }
+        let vertices = &self.vertices;
+        self.reachable.retain(|target, starts| {
+            starts.retain(|start, _| vertices.contains_key(start));
+            vertices.contains_key(target) && !starts.is_empty()
+        });
+        self.gc_round = self.gc_round.max(round);
+        removed
     }
-    
-    pub fn insert(&mut self, vertex: Vertex){
-        self.rounds.entry(vertex.round).or_default().push(vertex.clone());
-        self.vertices.insert(vertex.hash, self.rounds.get(&vertex.round).unwrap().last().unwrap().clone());
+
+    /// Whether the vertices of `round` were garbage collected.
+    pub fn is_pruned(&self, round: u64) -> bool {
+        round < self.gc_round
     }
 
-    pub fn get_round(&self, round : u64) -> Option<&Vec<Vertex>> {
+    pub fn get_round(&self, round : u64) -> Option<&Round> {
             self.rounds.get(&round)
     }
-    pub fn get_vertices_by_sources(&self, round: u64, sources: &[NodeId]) -> Vec<Vertex> {
-        let mut result = Vec::new();
-        let sources_set: HashSet<_> = sources.iter().collect();
-        if let Some(round_vertices) = self.rounds.get(&round)  {
-            for vertex in round_vertices {
-                if sources_set.contains(&vertex.clone().source){
-                    result.push(vertex.clone());
+
+    /// The vertex of `source` in `round`, if we hold it.
+    pub fn get(&self, round: u64, source: NodeId) -> Option<&Arc<Vertex>> {
+        self.rounds.get(&round).and_then(|vertices| vertices.get(&source))
+    }
+
+    pub fn get_vertices_by_sources(&self, round: u64, sources: &[NodeId]) -> Vec<&Arc<Vertex>> {
+        sources.iter().filter_map(|source| self.get(round, *source)).collect()
+    }
+
+    /// Whether `target_vertex` is in the causal history of `start_vertex`.
+    /// Pruned rounds can't be searched, so a pruned target is never reached.
+    pub fn has_path(&mut self, start_vertex: &Vertex, target_vertex: &Vertex) -> bool {
+        if start_vertex.hash == target_vertex.hash {
+            return true;
+        }
+        if self.is_pruned(target_vertex.round) || start_vertex.round <= target_vertex.round {
+            return false;
+        }
+        if let Some(&known) = self.reachable.get(&target_vertex.hash).and_then(|starts| starts.get(&start_vertex.hash)) {
+            return known;
+        }
+        let reachable = self.search(start_vertex, target_vertex);
+        self.reachable.entry(target_vertex.hash).or_default().insert(start_vertex.hash, reachable);
+        reachable
+    }
+
+    /// Breadth-first search from `start` down to the round of `target`,
+    /// cutting it short at vertices we already know the answer for.
+    fn search(&self, start: &Vertex, target: &Vertex) -> bool {
+        let known = self.reachable.get(&target.hash);
+        let mut visited: HashSet<&VertexHash> = HashSet::new();
+        let mut queue: VecDeque<&Vertex> = VecDeque::new();
+        visited.insert(&start.hash);
+        queue.push_back(start);
+
+        while let Some(current) = queue.pop_front() {
+            for parent_hash in &current.edges {
+                if *parent_hash == target.hash {
+                    return true;
+                }
+                if !visited.insert(parent_hash) {
+                    continue;
+                }
+                match known.and_then(|starts| starts.get(parent_hash)) {
+                    Some(true) => return true,
+                    Some(false) => continue,
+                    None => {}
+                }
+                // Don't search past the target's round
+                if let Some(parent) = self.vertices.get(parent_hash).filter(|parent| parent.round > target.round) {
+                    queue.push_back(parent);
                 }
             }
         }
-        result
+
+        false
     }
-    pub fn has_path(&self, start_vertex: &Vertex, target_vertex: &Vertex) -> bool {
-       if start_vertex.hash == target_vertex.hash {
-            return true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::digest::{Digest, DIGEST_LENGTH};
+
+    /// An unsigned vertex whose hash is just `id`: the DAG doesn't check either.
+    fn vertex(id: u8, round: u64, source: NodeId, edges: &[u8]) -> Vertex {
+        Vertex {
+            hash: Digest([id; DIGEST_LENGTH]),
+            round,
+            source,
+            block: vec![id],
+            edges: edges.iter().map(|edge| Digest([*edge; DIGEST_LENGTH])).collect(),
+            signed_round: vec![],
+            sample_proof: vec![],
+            signature: vec![],
         }
+    }
 
-        let mut queue = VecDeque::new();
-        let mut visited = HashSet::new();
+    #[test]
+    fn inserts_a_vertex_into_both_indexes() {
+        let mut dag = DAG::new();
+        assert!(dag.insert(vertex(1, 1, 0, &[])).unwrap());
+        assert_eq!(dag.get(1, 0).map(|v| v.hash), Some(Digest([1; DIGEST_LENGTH])));
+        assert!(dag.vertices.contains_key(&Digest([1; DIGEST_LENGTH])));
+    }
 
-        queue.push_back(start_vertex.clone());
-        visited.insert(start_vertex.hash.clone());
+    #[test]
+    fn inserting_a_vertex_again_is_a_no_op() {
+        let mut dag = DAG::new();
+        dag.insert(vertex(1, 1, 0, &[])).unwrap();
+        assert!(!dag.insert(vertex(1, 1, 0, &[])).unwrap());
+        assert_eq!(dag.vertices.len(), 1);
+        assert_eq!(dag.get_round(1).map(|round| round.len()), Some(1));
+    }
 
-        while let Some(current) = queue.pop_front() {
-            if current.hash == target_vertex.hash {
-                return true;
-            }
+    #[test]
+    fn rejects_a_second_vertex_of_a_source_in_a_round() {
+        let mut dag = DAG::new();
+        dag.insert(vertex(1, 1, 0, &[])).unwrap();
+        let equivocation = dag.insert(vertex(2, 1, 0, &[])).unwrap_err();
+        assert_eq!((equivocation.source, equivocation.round), (0, 1));
+        assert_eq!(equivocation.first.hash, Digest([1; DIGEST_LENGTH]));
+        assert_eq!(equivocation.second.hash, Digest([2; DIGEST_LENGTH]));
+
+        // The DAG keeps the first vertex only.
+        assert_eq!(dag.get(1, 0).map(|v| v.hash), Some(Digest([1; DIGEST_LENGTH])));
+        assert!(!dag.vertices.contains_key(&Digest([2; DIGEST_LENGTH])));
+        assert_eq!(dag.vertices.len(), 1);
+    }
+
+    /// A chain of one vertex per round, `1..=rounds`, vertex `r` linking to `r - 1`.
+    fn chain(dag: &mut DAG, rounds: u8) -> Vec<Arc<Vertex>> {
+        for round in 1..=rounds {
+            dag.insert(vertex(round, round as u64, 0, &[round - 1])).unwrap();
+        }
+        (1..=rounds).map(|round| dag.get(round as u64, 0).unwrap().clone()).collect()
+    }
+
+    #[test]
+    fn prunes_the_rounds_below() {
+        let mut dag = DAG::new();
+        chain(&mut dag, 4);
+        let mut removed: Vec<u64> = dag.prune_below(3).iter().map(|v| v.round).collect();
+        removed.sort_unstable();
+        assert_eq!(removed, [1, 2]);
+        assert_eq!(dag.gc_round, 3);
+        assert!(dag.get_round(2).is_none());
+        assert!(!dag.vertices.contains_key(&Digest([2; DIGEST_LENGTH])));
+        assert!(dag.get(3, 0).is_some());
+        assert!(dag.is_pruned(2));
+        assert!(!dag.is_pruned(3));
+
+        // The garbage collected round never goes back down.
+        assert!(dag.prune_below(1).is_empty());
+        assert_eq!(dag.gc_round, 3);
+    }
 
-            // Don't search past the target's round
-            if current.round <= target_vertex.round {
-                continue;
+    #[test]
+    fn ignores_a_vertex_of_a_pruned_round() {
+        let mut dag = DAG::new();
+        chain(&mut dag, 4);
+        dag.prune_below(3);
+        assert!(!dag.insert(vertex(9, 2, 1, &[1])).unwrap());
+        assert!(dag.get_round(2).is_none());
+        assert!(!dag.vertices.contains_key(&Digest([9; DIGEST_LENGTH])));
+    }
+
+    #[test]
+    fn never_reaches_a_pruned_target() {
+        let mut dag = DAG::new();
+        let chain = chain(&mut dag, 4);
+        assert!(dag.has_path(&chain[3], &chain[0]));
+        dag.prune_below(2);
+        assert!(!dag.has_path(&chain[3], &chain[0]));
+        assert!(dag.has_path(&chain[3], &chain[1]));
+    }
+
+    /// Rounds `1..=8` of four sources, each vertex linking to its own source's
+    /// previous vertex and, every third vertex, to its neighbour's as well.
+    fn sparse() -> (DAG, Vec<Arc<Vertex>>) {
+        let mut dag = DAG::new();
+        for round in 1..=8u8 {
+            for source in 0..4u8 {
+                let mut edges = vec![(round - 1) * 4 + source];
+                if (round + source) % 3 == 0 {
+                    edges.push((round - 1) * 4 + (source + 1) % 4);
+                }
+                dag.insert(vertex(round * 4 + source, round as u64, source as NodeId, &edges)).unwrap();
             }
+        }
+        let mut vertices: Vec<Arc<Vertex>> = dag.vertices.values().cloned().collect();
+        vertices.sort_by_key(|v| (v.round, v.source));
+        (dag, vertices)
+    }
 
-            for parent_hash in &current.edges {
-                if !visited.contains(parent_hash) {
-                    if let Some(parent_vertex) = self.vertices.get(parent_hash) {
-                        visited.insert(parent_hash.clone());
-                        queue.push_back(parent_vertex.clone());
-                    }
+    /// Checks every answer of `dag.has_path`, asked twice and lowest start
+    /// round first so that later searches go through cached answers, against
+    /// an uncached search of `fresh`. Returns how many were true and false.
+    fn compare(dag: &mut DAG, fresh: &DAG, vertices: &[Arc<Vertex>]) -> (usize, usize) {
+        let mut answers = (0, 0);
+        for _ in 0..2 {
+            for start in vertices {
+                for target in vertices.iter().filter(|target| target.hash != start.hash) {
+                    let expected = !fresh.is_pruned(target.round) && fresh.search(start, target);
+                    assert_eq!(dag.has_path(start, target), expected, "{:?} to {:?}", start.hash, target.hash);
+                    if expected { answers.0 += 1 } else { answers.1 += 1 }
                 }
             }
         }
+        answers
+    }
 
-        false
+    #[test]
+    fn cached_answers_match_a_fresh_search() {
+        let (mut dag, vertices) = sparse();
+        let (fresh, _) = sparse();
+        let (reached, unreached) = compare(&mut dag, &fresh, &vertices);
+        assert!(reached > 0 && unreached > 0);
+        assert!(!dag.reachable.is_empty());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn pruning_drops_the_cached_answers_of_pruned_vertices() {
+        let (mut dag, vertices) = sparse();
+        let (mut fresh, _) = sparse();
+        compare(&mut dag, &fresh, &vertices);
+        dag.prune_below(4);
+        fresh.prune_below(4);
+
+        assert!(!dag.reachable.is_empty());
+        for (target, starts) in &dag.reachable {
+            assert!(dag.vertices.contains_key(target));
+            assert!(starts.keys().all(|start| dag.vertices.contains_key(start)));
+        }
+        let live: Vec<Arc<Vertex>> = vertices.into_iter().filter(|v| v.round >= 4).collect();
+        compare(&mut dag, &fresh, &live);
+    }
+}
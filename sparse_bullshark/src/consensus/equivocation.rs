@@ -0,0 +1,58 @@
+use super::node::DagNode;
+use super::policy::{CommitRule, EdgePolicy};
+use crate::network::message::{Outgoing, Recipient, SparseMessage};
+use crate::types::equivocation::{Equivocation, EquivocationEvidence};
+use crate::types::vertex::NodeId;
+use log::{error, warn};
+use tokio::sync::mpsc::Sender;
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+    /// Signs an equivocation our DAG rejected and queues it for our peers.
+    /// Only the first one per source is kept: one is enough to convict it.
+    pub fn report_equivocation(&mut self, equivocation: Box<Equivocation>) {
+        let my_id = self.environment.my_node.id;
+        warn!(
+            "[Node {}] Node {} EQUIVOCATED in round {}: kept {:.8}, rejected {:.8}",
+            my_id, equivocation.source, equivocation.round,
+            equivocation.first.hash, equivocation.second.hash
+        );
+        if self.equivocations.contains_key(&equivocation.source) {
+            return;
+        }
+        let evidence = EquivocationEvidence::sign(*equivocation, my_id, &self.private_key);
+        self.equivocations.insert(evidence.equivocation.source, evidence.clone());
+        self.unannounced_equivocations.push(evidence);
+    }
+
+    /// Sends the evidence found since the last call to every peer.
+    pub async fn announce_equivocations(&mut self, dispatcher_tx: &Sender<Outgoing>) {
+        for evidence in std::mem::take(&mut self.unannounced_equivocations) {
+            if dispatcher_tx.send((Recipient::All, SparseMessage::Equivocation(Box::new(evidence)))).await.is_err() {
+                error!("[Node {}] Failed to send equivocation evidence to dispatcher.", self.environment.my_node.id);
+            }
+        }
+    }
+
+    /// Records evidence found by a peer once it checks out. Any honest node
+    /// that finds an equivocation announces it, so it is not passed on.
+    pub fn handle_equivocation(&mut self, sender: NodeId, evidence: EquivocationEvidence) {
+        let my_id = self.environment.my_node.id;
+        let source = evidence.equivocation.source;
+        if self.equivocations.contains_key(&source) {
+            return;
+        }
+        let verified = match (self.public_keys.get(&evidence.reporter), self.public_keys.get(&source)) {
+            (Some(reporter_key), Some(source_key)) => evidence.verify(reporter_key, source_key, E::VARIANT),
+            _ => false,
+        };
+        if !verified {
+            warn!("[Node {}] Discarding invalid equivocation evidence from Node {}.", my_id, sender);
+            return;
+        }
+        warn!(
+            "[Node {}] Node {} reported that Node {} EQUIVOCATED in round {}",
+            my_id, evidence.reporter, source, evidence.equivocation.round
+        );
+        self.equivocations.insert(source, evidence);
+    }
+}
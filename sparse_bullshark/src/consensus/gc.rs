@@ -0,0 +1,99 @@
+use super::node::DagNode;
+use super::policy::{CommitRule, EdgePolicy};
+use log::{debug, warn};
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+    /// Lowest round we keep: `gc_depth` rounds below the last ordered anchor.
+    /// Anchors ordered later are above it, so nothing they could still order
+    /// (see order_history) is dropped.
+    pub fn gc_round(&self) -> u64 {
+        self.last_ordered_round.saturating_sub(self.config.gc_depth)
+    }
+
+    /// Drops the rounds below `gc_round` from the DAG, together with the
+    /// ordering, RBC, pending and sync state of the same age.
+    pub fn garbage_collect(&mut self) {
+        let gc_round = self.gc_round();
+        if gc_round <= self.dag.gc_round {
+            return;
+        }
+        let removed = self.dag.prune_below(gc_round);
+        for vertex in &removed {
+            self.already_ordered.remove(&vertex.hash);
+        }
+        self.pending_vertices.prune_below(gc_round);
+
+        // RBC and sync state is keyed by hash only, so it's aged by our own
+        // round when it was created. An instance that hasn't finished within
+        // gc_depth rounds never will for a correct sender.
+        let mut expired = Vec::new();
+        self.rbc_seen.retain(|hash, seen| {
+            let keep = *seen >= gc_round;
+            if !keep {
+                expired.push(*hash);
+            }
+            keep
+        });
+        for hash in &expired {
+            self.echo_counts.remove(hash);
+            self.ready_counts.remove(hash);
+            self.delivered_vertices.remove(hash);
+            self.pending_rbc_vertices.remove(hash);
+        }
+        self.prune_sync_requests(gc_round);
+        // Recovery orders again from the last snapshot, which needs the same
+        // history below it as ordering it the first time did.
+        let storage_round = gc_round.min(self.last_snapshot_round.saturating_sub(self.config.gc_depth));
+        if let Err(e) = self.storage.prune_below(storage_round) {
+            // The log just keeps the extra records until the next attempt.
+            warn!("[Node {}] Failed to compact storage: {}", self.environment.my_node.id, e);
+        }
+
+        debug!("[Node {}] Garbage collected {} vertices and {} RBC instances below round {}", self.environment.my_node.id, removed.len(), expired.len(), gc_round);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::NodeConfig;
+    use crate::consensus::testing::{dense_node, dense_rounds};
+    use crate::types::digest::{Digest, DIGEST_LENGTH};
+    use crate::network::message::VertexMessage;
+
+    #[test]
+    fn drops_everything_below_the_gc_round() {
+        let config = NodeConfig { gc_depth: 10, ..NodeConfig::default() };
+        let (mut node, keypairs) = dense_node(4, 0, config);
+        // State of round 1, which garbage collection has to clean up as well.
+        let stale = Digest([7; DIGEST_LENGTH]);
+        node.rbc_seen.insert(stale, 1);
+        node.echo_counts.entry(stale).or_default().insert(1);
+        node.track_missing_parents(1, vec![stale], false);
+        let mut orphan = dense_rounds(&node, &keypairs, 2).pop().unwrap();
+        orphan.edges = vec![stale];
+        node.pending_vertices.insert(1, VertexMessage { sender: 1, vertex: orphan }, vec![stale]);
+
+        for vertex in dense_rounds(&node, &keypairs, 40) {
+            node.accept_vertex(vertex).unwrap();
+        }
+        assert_eq!(node.last_ordered_round, 38);
+        assert_eq!(node.dag.gc_round, 28);
+        assert!(node.dag.rounds.keys().all(|round| *round >= 28));
+        assert!(node.dag.vertices.values().all(|vertex| vertex.round >= 28));
+        assert!(node.already_ordered.iter().all(|hash| node.dag.vertices.contains_key(hash)));
+        assert!(!node.rbc_seen.contains_key(&stale) && !node.echo_counts.contains_key(&stale));
+        assert!(node.sync_requests.is_empty());
+        assert_eq!(node.pending_vertices.len(), 0);
+    }
+
+    #[test]
+    fn keeps_what_later_anchors_may_still_order() {
+        let config = NodeConfig { gc_depth: 10, ..NodeConfig::default() };
+        let (mut node, keypairs) = dense_node(4, 0, config);
+        for vertex in dense_rounds(&node, &keypairs, 40) {
+            node.accept_vertex(vertex).unwrap();
+        }
+        // Everything from gc_depth below the last ordered anchor on is still here.
+        assert!((28..=40).all(|round| node.dag.get_round(round).is_some_and(|vertices| vertices.len() == 4)));
+    }
+}
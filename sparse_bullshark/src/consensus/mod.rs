@@ -1,5 +1,14 @@
 pub mod bullshark;
 pub mod sparse_bullshark;
+pub mod node;
+pub mod policy;
 pub mod dag;
 pub mod ordering;
-pub mod ordering_bullshark;
\ No newline at end of file
+pub mod sync;
+pub mod pending;
+pub mod gc;
+pub mod recovery;
+pub mod equivocation;
+
+#[cfg(test)]
+pub mod testing;
@@ -0,0 +1,897 @@
+use std::{collections::HashMap, collections::HashSet, io, sync::Arc};
+use bincode::deserialize;
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use log::{error, info, warn,debug};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Sender},
+    time::{sleep, sleep_until, timeout, Duration, Instant},
+};
+use shared::{domain::{environment::Environment, node::Node}, hasher::{DefaultHasher, Hasher}, transaction_generator::TransactionGenerator};
+use crate::{
+    config::NodeConfig,
+    consensus::{dag::DAG, pending::PendingVertices, policy::{CommitRule, EdgePolicy}},
+    consensus::sync::MissingVertex,
+    execution::{NoopMachine, StateMachine},
+    storage::{NoStorage, Record, Storage},
+    network::{broadcast::generate_nonce, message::{Outgoing, Recipient, SparseMessage, VertexMessage}},
+    types::{committed::CommittedSubDag, equivocation::EquivocationEvidence, digest::Digest, vertex::{NodeId, Vertex, VertexHash, GENESIS_HASH}},
+};
+
+const NONCE_BYTES_LENGTH: usize = 32;
+const SIGNATURE_BYTES_LENGTH: usize = 64;
+const MESSAGE_BYTES_LENGTH: usize = 4;
+/// Bytes of the hash function's name in the handshake, zero-padded.
+const HASHER_NAME_BYTES_LENGTH: usize = 16;
+
+/// Our hash function's name as sent in the handshake: nodes hashing
+/// differently can't agree on any vertex, so they don't connect.
+fn hasher_name() -> [u8; HASHER_NAME_BYTES_LENGTH] {
+    let mut name = [0u8; HASHER_NAME_BYTES_LENGTH];
+    name[..DefaultHasher::NAME.len()].copy_from_slice(DefaultHasher::NAME.as_bytes());
+    name
+}
+
+/// A DAG-based consensus node. Networking, reliable broadcast, validation and
+/// ordering are shared by every protocol variant; the variant itself is defined
+/// by how edges are chosen/checked (`E`) and by the direct commit rule (`C`).
+pub struct DagNode<E: EdgePolicy, C: CommitRule> {
+    pub environment: Environment,
+    /// Timeouts, limits and paths, see `config`.
+    pub config: NodeConfig,
+    pub dag: DAG,
+    pub f: usize,
+    pub edge_policy: E,
+    pub commit_rule: C,
+    pub public_keys: HashMap<NodeId, PublicKey>,
+    transaction_generator: TransactionGenerator,
+    pub(super) private_key: Arc<Keypair>,
+    pub round: u64,
+    /// The last round we signed a vertex for. We never propose for it or
+    /// any earlier round again, also not after a restart.
+    pub last_proposed_round: u64,
+    /// Highest round of any vertex delivered to us.
+    pub highest_round_seen: u64,
+    round_started: Instant,
+    round_timer_fired: bool,
+    pub last_ordered_round: u64,
+    /// Round of the last state machine snapshot written to storage.
+    pub last_snapshot_round: u64,
+    pub ordered_anchors_stack: Vec<Arc<Vertex>>,
+    pub finalized_block_count: usize,
+    /// Delivered vertices waiting for parents, indexed by the missing parents.
+    pub pending_vertices: PendingVertices,
+    pub already_ordered: HashSet<VertexHash>,
+    pub total_bytes_created: u64,
+    pub echo_counts : HashMap<VertexHash, HashSet<NodeId>>,
+    pub ready_counts: HashMap<VertexHash, HashSet<NodeId>>,
+    pub delivered_vertices: HashSet<VertexHash>,
+    pub pending_rbc_vertices: HashMap<VertexHash, Vertex>,
+    /// Our round when each RBC instance was first heard of, for garbage collection.
+    pub rbc_seen: HashMap<VertexHash, u64>,
+    /// Missing parents and RBC bodies that we are fetching from peers.
+    pub sync_requests: HashMap<VertexHash, MissingVertex>,
+    /// Where our own vertices, the DAG, RBC votes and ordering progress are
+    /// written before we act on them. Nothing is kept by default.
+    pub storage: Box<dyn Storage>,
+    /// Where committed sub-DAGs go, see `subscribe`.
+    pub committed_tx: Option<Sender<CommittedSubDag>>,
+    /// Sub-DAGs committed since they were last sent to the subscriber.
+    pub unsent_sub_dags: Vec<CommittedSubDag>,
+    /// Executes the blocks of every committed sub-DAG, in commit order.
+    pub state_machine: Box<dyn StateMachine>,
+    /// Proof against every node known to have equivocated, found by us or
+    /// reported by a peer, for logging and penalising it.
+    pub equivocations: HashMap<NodeId, EquivocationEvidence>,
+    /// Evidence we found that our peers haven't been sent yet.
+    pub unannounced_equivocations: Vec<EquivocationEvidence>,
+}
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+    pub fn with_policy(
+        environment: Environment,
+        config: NodeConfig,
+        public_keys: HashMap<NodeId, PublicKey>,
+        private_key: Keypair,
+        edge_policy: E,
+        commit_rule: C,
+    ) -> Self {
+        let n = environment.nodes.len();
+        let f = (n.saturating_sub(1)) / 3;
+        let transaction_size = environment.transaction_size;
+        let n_transactions = environment.n_transactions;
+        let max_pending_per_sender = config.limits.max_pending_per_sender;
+        let mut node = DagNode {
+            environment,
+            config,
+            dag: DAG::new(),
+            f,
+            edge_policy,
+            commit_rule,
+            public_keys,
+            transaction_generator: TransactionGenerator::new(
+            transaction_size,
+            n_transactions,
+            ),
+            private_key: Arc::new(private_key),
+            round: 1,
+            last_proposed_round: 0,
+            highest_round_seen: 0,
+            round_started: Instant::now(),
+            round_timer_fired: false,
+            last_ordered_round: 0,
+            last_snapshot_round: 0,
+            ordered_anchors_stack: Vec::new(),
+            finalized_block_count: 0,
+            pending_vertices: PendingVertices::new(max_pending_per_sender),
+            already_ordered : HashSet::new(),
+            total_bytes_created: 0,
+            echo_counts : HashMap::new(),
+            ready_counts : HashMap::new(),
+            delivered_vertices : HashSet::new(),
+            pending_rbc_vertices : HashMap::new(),
+            rbc_seen : HashMap::new(),
+            sync_requests : HashMap::new(),
+            storage: Box::new(NoStorage),
+            committed_tx: None,
+            unsent_sub_dags: Vec::new(),
+            state_machine: Box::new(NoopMachine),
+            equivocations: HashMap::new(),
+            unannounced_equivocations: Vec::new(),
+        };
+        node.add_genesis_block();
+        node
+    }
+
+    async fn process_work_loop(&mut self, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        // Buffered vertices are released by their parents (see accept_vertex),
+        // so the only work left here is advancing the round as far as we can.
+        self.catch_up();
+        while self.may_advance_round() {
+            if self.round <= self.last_proposed_round {
+                error!("[Node {}] Refusing to propose again for round {}", self.environment.my_node.id, self.round);
+                break;
+            }
+            debug!("[Node {}] Advancing to round {}", self.environment.my_node.id, self.round);
+            let new_vertex = self.create_new_vertex(self.round);
+            let my_id = self.environment.my_node.id;
+            
+            // Once broadcast, this is our vertex for the round: it has to
+            // survive a crash so that we never sign a different one.
+            self.persist(&Record::Proposal(new_vertex.clone()))?;
+            self.last_proposed_round = new_vertex.round;
+            if self.dag.insert(new_vertex.clone()).is_err() {
+                error!("[Node {}] Our vertex for round {} conflicts with one already in the DAG", my_id, new_vertex.round);
+            }
+            self.round += 1;
+            self.round_started = Instant::now();
+            self.round_timer_fired = false;
+            
+            let vertex_message = SparseMessage::Vertex(VertexMessage {
+                sender: my_id,
+                vertex: new_vertex.clone(),
+            });
+            
+            if dispatcher_tx.send((Recipient::All, vertex_message)).await.is_err() {
+                error!("[Node {}] Failed to send vertex to dispatcher.", self.environment.my_node.id);
+            }
+            self.handle_rbc_val(my_id, new_vertex.clone(), dispatcher_tx).await?;
+        }
+        Ok(())
+    }
+
+    /// Jumps ahead when a quorum of the committee is more than a round ahead
+    /// of us, e.g. after a restart: there is no point in proposing for rounds
+    /// everyone else has left behind.
+    fn catch_up(&mut self) {
+        let quorum_threshold = 2 * self.f + 1;
+        let highest_quorum_round = self.dag.rounds.iter()
+            .filter(|(_, vertices)| vertices.len() >= quorum_threshold)
+            .map(|(round, _)| *round)
+            .max();
+        if let Some(round) = highest_quorum_round.filter(|round| *round > self.round) {
+            debug!("[Node {}] Catching up from round {} to round {}", self.environment.my_node.id, self.round, round + 1);
+            self.round = round + 1;
+            self.round_started = Instant::now();
+            self.round_timer_fired = false;
+        }
+    }
+
+    /// Inserts a validated vertex, then validates and inserts every buffered
+    /// vertex it (transitively) unblocks. A released vertex that is invalid
+    /// takes the vertices waiting on it along.
+    pub fn accept_vertex(&mut self, vertex: Vertex) -> io::Result<()> {
+        let mut inserted = vec![vertex.hash];
+        if !self.insert_vertex(vertex)? {
+            return Ok(());
+        }
+
+        while let Some(parent) = inserted.pop() {
+            for (sender_id, vm) in self.pending_vertices.resolve(&parent) {
+                if self.validate_vertex(&vm.vertex, vm.vertex.round, sender_id) {
+                    debug!("[Node {}] Pending vertex from Node {} in round {} is now VALID", self.environment.my_node.id, sender_id, vm.vertex.round);
+                    let hash = vm.vertex.hash;
+                    if self.insert_vertex(vm.vertex)? {
+                        inserted.push(hash);
+                    }
+                } else {
+                    // Whatever waits on it would never be released.
+                    let dropped = self.pending_vertices.reject(&vm.vertex.hash);
+                    warn!(
+                        "[Node {}] Discarding INVALID pending vertex from Node {} in round {} and {} vertices waiting on it.",
+                        self.environment.my_node.id, sender_id, vm.vertex.round, dropped
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Inserts a validated vertex into the DAG and storage and tries to
+    /// commit with it. A vertex conflicting with one we hold is reported as
+    /// an equivocation instead; returns whether it was inserted.
+    fn insert_vertex(&mut self, vertex: Vertex) -> io::Result<bool> {
+        match self.dag.insert(vertex.clone()) {
+            Ok(true) => {}
+            Ok(false) => return Ok(false),
+            Err(equivocation) => {
+                self.report_equivocation(equivocation);
+                return Ok(false);
+            }
+        }
+        self.persist(&Record::Vertex(vertex.clone()))?;
+        self.try_committing(&vertex)?;
+        Ok(true)
+    }
+
+    /// Writes `record` to storage. We can't go on without it: acting on
+    /// state we'd forget in a crash could make us equivocate after restarting.
+    /// The error is passed up to `start`, which stops the node.
+    pub fn persist(&mut self, record: &Record) -> io::Result<()> {
+        self.storage.append(record)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to write to storage: {}", e)))
+    }
+
+    fn add_genesis_block(&mut self) {
+        let genesis_vertex = Vertex {
+            hash: GENESIS_HASH,
+            round: 0,
+            source: 0,
+            block: vec![],
+            edges: vec![],
+            signed_round: vec![],
+            sample_proof: vec![],
+            signature: vec![],
+        };
+        self.dag.insert(genesis_vertex).expect("The genesis vertex can't conflict with an empty DAG");
+    }
+
+    /// Runs the node for `timeouts.run_secs`. Fails, stopping the node, if
+    /// storage can't be written.
+    pub async fn start(mut self) -> io::Result<()> {
+        let address = format!("{}:{}", self.environment.my_node.host, self.environment.my_node.port);
+        let listener = TcpListener::bind(&address).await.expect("Failed to bind local port");
+
+        debug!("[Node {}] Listening on {}", self.environment.my_node.id, &address);
+        let (message_tx, mut message_rx) = mpsc::channel(self.config.limits.channel_size);
+        let (dispatcher_tx, dispatcher_rx) = mpsc::channel(self.config.limits.channel_size);
+
+        let (connected_tx, mut connected_rx) = mpsc::channel(self.environment.nodes.len());
+        self.start_listener(listener, message_tx, connected_tx);
+
+        debug!("[Node {}] Waiting for all nodes to connect...", self.environment.my_node.id);
+        sleep(self.config.timeouts.startup_delay()).await;
+        self.start_message_dispatcher(dispatcher_rx);
+
+        // A fresh committee starts together. A node rejoining after a crash
+        // only needs enough peers to make progress with; the others are
+        // running already and will reconnect to it on their own.
+        let rejoining = self.last_proposed_round > 0;
+        let needed = if rejoining { 2 * self.f } else { self.environment.nodes.len() - 1 };
+        let mut connected = HashSet::new();
+        while connected.len() < needed {
+            match connected_rx.recv().await {
+                Some(peer) => { connected.insert(peer); }
+                None => break,
+            }
+        }
+        debug!("[Node {}] All nodes connected. Starting protocol.", self.environment.my_node.id);
+
+        if rejoining {
+            self.resume(&dispatcher_tx).await;
+        }
+
+        let mut sync_interval = tokio::time::interval(self.config.timeouts.sync_retry());
+        let execution_duration = self.config.timeouts.run();
+        let start_time = Instant::now();
+
+        self.process_work_loop(&dispatcher_tx).await?;
+        self.send_committed().await;
+
+        // Now we start the main loop, listening for messages from peers.
+        // The round timer wakes us up even if no message arrives, so that we
+        // stop waiting for a slow leader once the timeout has passed.
+        while start_time.elapsed() < execution_duration {
+            let round_deadline = self.round_started + self.config.timeouts.round();
+            tokio::select! {
+                received = message_rx.recv() => {
+                    match received {
+                        Some((sender_id, message)) => self.handle_message(sender_id, message, &dispatcher_tx).await?,
+                        None => break,
+                    }
+                }
+                _ = sleep_until(round_deadline), if !self.round_timer_fired => {
+                    debug!("[Node {}] Round {} timer expired", self.environment.my_node.id, self.round);
+                    self.round_timer_fired = true;
+                    self.process_work_loop(&dispatcher_tx).await?;
+                }
+                _ = sync_interval.tick() => {
+                    self.retry_sync_requests(&dispatcher_tx).await;
+                }
+            }
+            // Outside the select, so a full channel holds the loop up
+            // rather than losing a sub-DAG to a cancelled send.
+            self.send_committed().await;
+        }
+
+        debug!("[Node {}] Execution finished after {} seconds.", self.environment.my_node.id, start_time.elapsed().as_secs());
+        self.print_dag_stats();
+        println!("[Node {}] Final ordered round: {}", self.environment.my_node.id, self.last_ordered_round);
+        println!("Blocks finalized: {}", self.finalized_block_count);
+        println!("Total data created: {} MB", self.total_bytes_created/(1024*1024));
+        // Allow some time for final messages to flush
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        Ok(())
+    }
+
+    async fn handle_message(&mut self, sender_id: NodeId, message: SparseMessage, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        match message {
+            SparseMessage::Vertex(vm) => {
+                // This acts as the RBC VAL message
+                self.handle_rbc_val(sender_id, vm.vertex, dispatcher_tx).await?;
+            },
+            SparseMessage::RbcEcho(echo) => {
+                self.handle_rbc_echo(sender_id, echo.vertex_hash, dispatcher_tx).await?;
+            },
+            SparseMessage::RbcReady(ready) => {
+                self.handle_rbc_ready(sender_id, ready.vertex_hash, dispatcher_tx).await?;
+            },
+            SparseMessage::Commit(_) => {
+                // Every node orders the DAG on its own, so there is nothing to do.
+            },
+            SparseMessage::SyncRequest(request) => {
+                self.handle_sync_request(sender_id, request.hashes, dispatcher_tx).await;
+            },
+            SparseMessage::SyncResponse(response) => {
+                self.handle_sync_response(sender_id, response.vertices, dispatcher_tx).await?;
+            },
+            SparseMessage::Equivocation(evidence) => {
+                self.handle_equivocation(sender_id, *evidence);
+            }
+        }
+        Ok(())
+    }
+
+    /// Accepts connections from peers for as long as the node runs, so a peer
+    /// that restarts can connect again. The id of every authenticated peer is
+    /// reported on `connected`.
+    fn start_listener(&self, listener: TcpListener, message_sender: Sender<(NodeId, SparseMessage)>, connected: Sender<NodeId>) {
+        let public_keys = self.public_keys.clone();
+        let my_id = self.environment.my_node.id;
+        let test_flag = self.environment.test_flag;
+        let max_frame_bytes = self.config.limits.max_frame_bytes;
+        tokio::spawn(async move {
+            loop {
+                let Ok((mut stream, _)) = listener.accept().await else { continue };
+                let mut id_buf = [0u8; 4];
+                if stream.read_exact(&mut id_buf).await.is_err() { continue; }
+                let claimed_id = u32::from_be_bytes(id_buf);
+                let mut nonce = vec![0u8; NONCE_BYTES_LENGTH];
+                if stream.read_exact(&mut nonce).await.is_err() { continue; }
+                let mut sig_bytes = vec![0u8; SIGNATURE_BYTES_LENGTH];
+                if stream.read_exact(&mut sig_bytes).await.is_err() { continue; }
+                let mut peer_hasher = [0u8; HASHER_NAME_BYTES_LENGTH];
+                if stream.read_exact(&mut peer_hasher).await.is_err() { continue; }
+
+                if let Some(key) = public_keys.get(&claimed_id) {
+                    if let Ok(signature) = Signature::from_bytes(&sig_bytes) {
+                        if key.verify(&nonce, &signature).is_ok() {
+                            if peer_hasher != hasher_name() {
+                                error!(
+                                    "[Node {}] Refusing Node {}: it hashes with {}, we hash with {}; every node must be built with the same hasher",
+                                    my_id, claimed_id, String::from_utf8_lossy(&peer_hasher).trim_end_matches('\0'), DefaultHasher::NAME
+                                );
+                                continue;
+                            }
+                            let msg_sender = message_sender.clone();
+                            let pks = public_keys.clone();
+                            tokio::spawn(async move {
+                                Self::handle_connection(stream, msg_sender, my_id, claimed_id, pks, test_flag, max_frame_bytes).await;
+                            });
+                            // Nobody listens anymore once the node is running.
+                            let _ = connected.try_send(claimed_id);
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /// Opens our outgoing connection to `node` and authenticates it. The
+    /// handshake also names our hash function, see `hasher_name`.
+    async fn dial(node: &Node, my_id: NodeId, private_key: &Keypair, connect_timeout: Duration) -> Option<TcpStream> {
+        let address = format!("{}:{}", node.host, node.port);
+        let mut stream = timeout(connect_timeout, TcpStream::connect(&address)).await.ok()?.ok()?;
+        let nonce = generate_nonce();
+        let signature = private_key.sign(&nonce);
+        stream.write_all(&my_id.to_be_bytes()).await.ok()?;
+        stream.write_all(&nonce).await.ok()?;
+        stream.write_all(signature.as_ref()).await.ok()?;
+        stream.write_all(&hasher_name()).await.ok()?;
+        stream.flush().await.ok()?;
+        Some(stream)
+    }
+
+    async fn handle_connection(mut stream: TcpStream, 
+        message_sender: Sender<(NodeId, SparseMessage)>, 
+        my_id: NodeId, peer_id: NodeId, 
+        public_keys: HashMap<NodeId, PublicKey>, 
+        test_flag: bool,
+        max_frame_bytes: usize,
+    ) {
+        debug!("[Node {}] Listening for messages from Node {}", my_id, peer_id);
+        loop {
+            let mut length_bytes = [0u8; MESSAGE_BYTES_LENGTH];
+            if stream.read_exact(&mut length_bytes).await.is_err() {
+                error!("[Node {}] Connection dropped by Node {}", my_id, peer_id);
+                return;
+            }
+            let length = u32::from_be_bytes(length_bytes);
+            if length == 0 || length as usize > max_frame_bytes { return; }
+            let mut buffer = vec![0; length as usize];
+            if stream.read_exact(&mut buffer).await.is_err() { return; }
+            let mut verified = test_flag;
+            let mut sig_bytes = [0u8; 64];
+            if stream.read_exact(&mut sig_bytes).await.is_err() { return; }
+            if !test_flag {
+                if let Some(pubkey) = public_keys.get(&peer_id){
+                    if let Ok(sig) = Signature::from_bytes(&sig_bytes) {
+                        if pubkey.verify(&buffer, &sig).is_ok() {
+                            verified = true;
+                        }
+                    }
+                }
+            }
+            if verified {
+                if let Ok(message) = deserialize(&buffer) {
+                    if message_sender.send((peer_id, message)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+     }
+
+    /// Sends our messages to the peers. A connection that fails is dropped
+    /// and dialed again every `timeouts.reconnect_ms`, together with the peers
+    /// that were not up yet; messages to a peer without a connection are lost
+    /// and recovered by RBC and sync.
+    fn start_message_dispatcher(&self, mut dispatcher_receiver: mpsc::Receiver<Outgoing>) {
+        let private_key = self.private_key.clone();
+        let test_flag = self.environment.test_flag;
+        let my_id = self.environment.my_node.id;
+        let connect_timeout = self.config.timeouts.connect();
+        let reconnect_interval = self.config.timeouts.reconnect();
+        let peers: Vec<Node> = self.environment.nodes.iter().filter(|node| node.id != my_id).cloned().collect();
+        tokio::spawn(async move {
+            let mut connections: Vec<Option<TcpStream>> = (0..=peers.iter().map(|node| node.id as usize).max().unwrap_or(0)).map(|_| None).collect();
+            let mut reconnect = tokio::time::interval(reconnect_interval);
+            loop {
+                tokio::select! {
+                    received = dispatcher_receiver.recv() => {
+                        let Some((recipient, message)) = received else { break };
+                        let Ok(payload) = bincode::serialize(&message) else { continue };
+                        let signature = if !test_flag {
+                            private_key.sign(&payload)
+                        }else{
+                            Signature::from_bytes(&[0u8; 64]).unwrap()
+                        };
+                        let length_bytes = (payload.len() as u32).to_be_bytes();
+
+                        let targets: Vec<usize> = match recipient {
+                            Recipient::All => peers.iter().map(|node| node.id as usize).collect(),
+                            Recipient::Node(id) => vec![id as usize],
+                        };
+                        for id in targets {
+                            let Some(stream) = connections.get_mut(id).and_then(|c| c.as_mut()) else { continue };
+                            let written = async {
+                                stream.write_all(&length_bytes).await?;
+                                stream.write_all(&payload).await?;
+                                stream.write_all(signature.as_ref()).await
+                            }.await;
+                            if written.is_err() {
+                                warn!("[Node {}] Lost connection to Node {}, reconnecting", my_id, id);
+                                connections[id] = None;
+                            }
+                        }
+                    }
+                    _ = reconnect.tick() => {
+                        for node in &peers {
+                            if connections[node.id as usize].is_none() {
+                                connections[node.id as usize] = Self::dial(node, my_id, &private_key, connect_timeout).await;
+                                if connections[node.id as usize].is_some() {
+                                    debug!("[Node {}] Connected to Node {}", my_id, node.id);
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+        });
+    }
+    /// Handles a newly received vertex message.
+    /// If valid, it's processed. If invalid due to missing parents, it's buffered.
+    /// The vertex must have passed `is_signed_by_source`: the source signs the
+    /// hash of the whole vertex, so nobody relaying it could have changed it.
+    pub async fn handle_new_vertex_message(&mut self, sender_id: NodeId, vm: VertexMessage, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        // Our own vertices are inserted when created, and a vertex may arrive
+        // both through RBC and through sync.
+        if self.dag.vertices.contains_key(&vm.vertex.hash) || self.pending_vertices.contains(&vm.vertex.hash) {
+            return Ok(());
+        }
+        if self.dag.is_pruned(vm.vertex.round) {
+            debug!("[Node {}] Ignoring vertex from Node {} in garbage collected round {}.", self.environment.my_node.id, sender_id, vm.vertex.round);
+            return Ok(());
+        }
+        // Parents of a vertex behind the newest round we've seen won't show
+        // up through RBC anymore, so they are fetched right away.
+        let behind = vm.vertex.round < self.highest_round_seen;
+        self.highest_round_seen = self.highest_round_seen.max(vm.vertex.round);
+
+        // Parents are checked first: until they are all here the vertex can't
+        // be validated, and once they are its validity no longer changes.
+        let missing = self.missing_parents(&vm.vertex);
+        if !missing.is_empty() {
+            // Buffer it and fetch the parents if they don't show up on their own.
+            debug!("[Node {}] Buffering vertex from Node {} in round {} ({} parents missing).", self.environment.my_node.id, sender_id, vm.vertex.round, missing.len());
+            let to_fetch: Vec<VertexHash> = missing.iter().filter(|hash| !self.pending_vertices.contains(hash)).cloned().collect();
+            if !self.pending_vertices.insert(sender_id, vm, missing) {
+                warn!("[Node {}] Dropping vertex from Node {}: too many pending vertices from it.", self.environment.my_node.id, sender_id);
+                return Ok(());
+            }
+            self.track_missing_parents(sender_id, to_fetch, behind);
+            if behind {
+                self.retry_sync_requests(dispatcher_tx).await;
+            }
+        } else if self.validate_vertex(&vm.vertex, vm.vertex.round, sender_id) {
+            // It's valid: insert, commit, and then try to advance the protocol
+            debug!("[Node {}] Vertex from Node {} in round {} is VALID", self.environment.my_node.id, sender_id, vm.vertex.round);
+            self.accept_vertex(vm.vertex)?;
+            self.announce_equivocations(dispatcher_tx).await;
+
+            // Now, try to process any work this vertex may have unblocked
+            self.process_work_loop(dispatcher_tx).await?;
+        } else {
+            warn!("[Node {}] Discarding INVALID vertex from Node {} in round {}.", self.environment.my_node.id, sender_id, vm.vertex.round);
+        }
+        Ok(())
+    }
+    /// Bullshark's partially synchronous round rule. Besides a quorum of the
+    /// previous round we wait, until the round timer expires, for:
+    /// - even rounds: the leader's anchor;
+    /// - odd rounds: enough votes to commit the previous anchor, or a quorum
+    ///   of vertices that do not vote for it.
+    fn may_advance_round(&self) -> bool {
+        if self.round == 1 { return true; }
+        let quorum_threshold = 2 * self.f + 1;
+        let previous_round = self.round - 1;
+        let previous_vertices = match self.dag.get_round(previous_round) {
+            Some(vertices) if vertices.len() >= quorum_threshold => vertices,
+            _ => return false,
+        };
+        if self.round_started.elapsed() >= self.config.timeouts.round() {
+            return true;
+        }
+
+        if previous_round.is_multiple_of(2) {
+            self.get_anchor(previous_round).is_some()
+        } else {
+            let votes = match self.get_anchor(previous_round - 1) {
+                Some(anchor) => previous_vertices.values().filter(|v| v.edges.contains(&anchor.hash)).count(),
+                None => 0,
+            };
+            votes >= self.commit_rule.direct_commit_threshold(self.f)
+                || previous_vertices.len() - votes >= quorum_threshold
+        }
+    }
+
+    pub fn get_anchor(&self, r: u64) -> Option<&Arc<Vertex>> {
+        if r % 2 == 1 { return None; }
+        let leader_id = (r / 2) % self.environment.nodes.len() as u64;
+        self.dag.get(r, leader_id as NodeId)
+    }
+
+    fn create_new_vertex(&mut self, round: u64) -> Vertex {
+        let candidates: Vec<Arc<Vertex>> = self.dag.get_round(round - 1)
+            .map(|vertices| vertices.values().cloned().collect())
+            .unwrap_or_default();
+        let selection = self.edge_policy.select_parents(round, self.environment.my_node.id, &candidates, &self.private_key);
+        let mut edges_hashes: Vec<VertexHash> = selection.parents;
+        if let Some(anchor) = self.get_anchor(round - 1) {
+            if !edges_hashes.contains(&anchor.hash) {
+                edges_hashes.push(anchor.hash);
+            }
+        }
+        let mut new_vertex = Vertex {
+            hash: Digest::default(),
+            round,
+            source: self.environment.my_node.id,
+            block: bincode::serialize(&self.transaction_generator.generate()).expect("Failed to serialize block"),
+            edges: edges_hashes,
+            signed_round: selection.signed_round,
+            sample_proof: selection.sample_proof,
+            signature: vec![],
+        };
+        new_vertex.hash = new_vertex.calculate_hash(E::VARIANT);
+        new_vertex.sign(&self.private_key);
+        if let Ok(vertex_bytes) = bincode::serialize(&new_vertex){
+            self.total_bytes_created += vertex_bytes.len() as u64;
+        }  
+        new_vertex
+    }
+
+    /// Whether `vertex` is signed by the node it claims to come from.
+    pub fn is_signed_by_source(&self, vertex: &Vertex) -> bool {
+        self.public_keys.get(&vertex.source).is_some_and(|key| vertex.verify(key, E::VARIANT))
+    }
+
+    /// Checks a vertex against the protocol and our DAG. Its hash and
+    /// signature were already checked, once, where it entered the node (see
+    /// `handle_new_vertex_message`), so they aren't recomputed here.
+    fn validate_vertex(&self, v: &Vertex, round: u64, source: u32) -> bool {
+        // Special validation for Round 1
+        if v.round == 1 {
+            if v.edges.len() == 1 && v.edges[0] == GENESIS_HASH {
+                return self.check_edge_policy(v);
+            } else {
+                warn!("[Node {}] Round 1 vertex has an invalid link to the genesis block.", self.environment.my_node.id);
+                return false;
+            }
+        }
+
+        // --- General Validation for Rounds > 1 ---
+
+        // 1. Basic checks
+        if v.source != source || v.round != round {
+            warn!("[Node {}] Vertex failed basic validation: source/round mismatch.", self.environment.my_node.id);
+            return false;
+        }
+
+        // 2. Variant-specific edge/proof validation
+        if !self.check_edge_policy(v) {
+            return false;
+        }
+
+        // 3. Parent check: we must hold every parent the vertex links to.
+        // Parents in a garbage collected round can't be checked anymore.
+        let parent_round_number = v.round - 1;
+        if self.dag.is_pruned(parent_round_number) {
+            debug!("[Node {}] Parents of vertex in round {} were garbage collected.", self.environment.my_node.id, v.round);
+        } else {
+            for edge_hash in &v.edges {
+                // Each parent must be in the DAG, and in the previous round.
+                if self.dag.vertices.get(edge_hash).is_none_or(|parent| parent.round != parent_round_number) {
+                    warn!("[Node {}] Vertex failed validation: missing parent with hash {:?}.", self.environment.my_node.id, edge_hash);
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+    fn check_edge_policy(&self, v: &Vertex) -> bool {
+        let anchor = self.get_anchor(v.round - 1).map(|anchor| anchor.as_ref());
+        if let Err(reason) = self.edge_policy.validate_parents(v, &self.dag, anchor, &self.public_keys) {
+            warn!("[Node {}] Vertex failed validation: {}.", self.environment.my_node.id, reason);
+            return false;
+        }
+        true
+    }
+
+    async fn broadcast(&self, msg: SparseMessage, dispatcher_tx: &Sender<Outgoing>){
+        if dispatcher_tx.send((Recipient::All, msg)).await.is_err() {
+           error!("[Node {}] Failed to broadcast message: channel closed", self.environment.my_node.id);
+        }
+
+    }
+
+    pub async fn send_to(&self, peer: NodeId, msg: SparseMessage, dispatcher_tx: &Sender<Outgoing>){
+        if dispatcher_tx.send((Recipient::Node(peer), msg)).await.is_err() {
+           error!("[Node {}] Failed to send message to Node {}: channel closed", self.environment.my_node.id, peer);
+        }
+    }
+
+    async fn handle_rbc_val(&mut self, sender: NodeId, vertex :Vertex, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        let hash = vertex.hash;
+        
+        if self.delivered_vertices.contains(&hash) || self.dag.is_pruned(vertex.round) {
+            return Ok(());
+        }
+        // VALs are accepted from any relayer, so we only ECHO what the
+        // source itself signed.
+        if !self.is_signed_by_source(&vertex) {
+            warn!("[Node {}] Ignoring VAL from Node {}: vertex not signed by its source Node {}.", self.environment.my_node.id, sender, vertex.source);
+            return Ok(());
+        }
+        self.rbc_seen.entry(hash).or_insert(self.round);
+        if self.pending_rbc_vertices.contains_key(&hash) {
+            return Ok(());
+        }
+        // Perform basic validation before storing/voting (e.g. signature, format)
+        // Note: We don't check graph parents yet, just the vertex integrity.
+        self.pending_rbc_vertices.insert(hash, vertex);
+
+        // 2. Broadcast ECHO
+        // In Bracha's RBC, receiving a valid VAL triggers an ECHO.
+        self.persist(&Record::RbcEcho { hash, round: self.round })?;
+        let echo_msg = SparseMessage::RbcEcho(crate::network::message::EchoMessage {
+            vertex_hash: hash,
+        });
+        self.broadcast(echo_msg, dispatcher_tx).await;
+
+        // The dispatcher doesn't loop back to us, so count our own ECHO here.
+        self.handle_rbc_echo(self.environment.my_node.id, hash, dispatcher_tx).await
+    }
+    async fn handle_rbc_echo(&mut self, sender: NodeId, hash: VertexHash, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        if self.delivered_vertices.contains(&hash) {
+            return Ok(());
+        }
+        self.rbc_seen.entry(hash).or_insert(self.round);
+
+        let votes = self.echo_counts.entry(hash).or_default();
+        votes.insert(sender);
+
+        // Threshold to send READY: 2f + 1 ECHOs (Standard Bracha)
+        // Or n - f (Quorum). We use 2f+1 here as it is standard for asynchronous BFT.
+        let threshold = 2 * self.f + 1;
+        
+        if votes.len() >= threshold {
+            self.try_send_ready(hash, dispatcher_tx).await?;
+        }
+        Ok(())
+    }
+
+    async fn handle_rbc_ready(&mut self, sender: NodeId, hash: VertexHash, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        if self.delivered_vertices.contains(&hash) {
+            return Ok(());
+        }
+        self.rbc_seen.entry(hash).or_insert(self.round);
+        let votes = self.ready_counts.entry(hash).or_default();
+        votes.insert(sender);
+
+        let ready_count = votes.len();
+
+        // 1. Amplification Step: If we see f+1 READYs, we must also send READY
+        // This ensures liveness if correct nodes are split.
+        if ready_count > self.f {
+            self.try_send_ready(hash, dispatcher_tx).await?;
+        }
+        // Our own READY may have just completed the quorum.
+        let ready_count = self.ready_counts.get(&hash).map_or(0, |votes| votes.len());
+
+        // 2. Delivery Step: If we see 2f+1 READYs, we deliver.
+        let delivery_threshold = 2 * self.f + 1;
+        if ready_count >= delivery_threshold {
+            // Check if we have the body
+            if let Some(vertex) = self.pending_rbc_vertices.remove(&hash) {
+                debug!("[Node {}] RBC DELIVERED vertex from Node {} in round {}", self.environment.my_node.id, vertex.source, vertex.round);
+                
+                // Mark as delivered so we don't process it again
+                self.persist(&Record::RbcDelivered { hash, round: self.round })?;
+                self.echo_counts.remove(&hash);
+                self.ready_counts.remove(&hash);                
+                self.delivered_vertices.insert(hash);
+
+                self.handle_new_vertex_message(vertex.source, VertexMessage { sender: vertex.source, vertex }, dispatcher_tx).await?;
+            } else {
+                // We have the votes but not the body (we missed the VAL).
+                self.fetch_rbc_body(hash, dispatcher_tx).await;
+            }
+        }
+        Ok(())
+    }
+    /// Sends our READY for `hash` unless we already did: our own vote in
+    /// `ready_counts` records that it was sent.
+    async fn try_send_ready(&mut self, hash: VertexHash, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        let my_id = self.environment.my_node.id;
+        let sent = self.ready_counts.get(&hash).is_some_and(|votes| votes.contains(&my_id));
+        
+        if !sent {
+            self.persist(&Record::RbcReady { hash, round: self.round })?;
+            self.ready_counts.entry(hash).or_default().insert(my_id);
+            let ready_msg = SparseMessage::RbcReady(crate::network::message::ReadyMessage {
+                vertex_hash: hash,
+            });
+            self.broadcast(ready_msg, dispatcher_tx).await;
+        }
+        Ok(())
+    }
+    
+    fn print_dag_stats(&self) {
+        info!("--- [Node {}] FINAL DAG STATS ---", self.environment.my_node.id);
+
+        info!("Total vertices in 'dag.vertices': {}", self.dag.vertices.len());
+        let mut vertices_in_rounds = 0;
+        for round_vec in self.dag.rounds.values() {
+            vertices_in_rounds += round_vec.len();
+        }
+        info!("Total vertices in 'dag.rounds': {}", vertices_in_rounds);
+
+        info!("Total unique vertices in 'already_ordered': {}", self.already_ordered.len());
+        info!("Total pending vertices (unprocessed): {}", self.pending_vertices.len());
+        info!("Total vertices still being synced: {}", self.sync_requests.len());
+        
+        info!("--- END DAG STATS ---");
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::config::NodeConfig;
+    use crate::consensus::bullshark::DenseEdges;
+    use crate::consensus::testing::{dense_node, dense_rounds, vertex};
+    use crate::network::message::{Recipient, SparseMessage};
+    use crate::types::digest::{Digest, DIGEST_LENGTH};
+    use crate::types::vertex::GENESIS_HASH;
+    use tokio::sync::mpsc;
+
+    #[test]
+    fn accepts_parents_in_a_pruned_round() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        node.dag.prune_below(5);
+        let v = vertex::<DenseEdges>(&keypairs, 1, 5, vec![Digest([9; DIGEST_LENGTH])]);
+        assert!(node.validate_vertex(&v, 5, 1));
+    }
+
+    #[test]
+    fn rejects_an_unknown_parent_in_a_live_round() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        for v in dense_rounds(&node, &keypairs, 4) {
+            node.accept_vertex(v).unwrap();
+        }
+        let known = node.dag.get(4, 0).unwrap().hash;
+        assert!(node.validate_vertex(&vertex::<DenseEdges>(&keypairs, 1, 5, vec![known]), 5, 1));
+        let unknown = vertex::<DenseEdges>(&keypairs, 1, 5, vec![known, Digest([9; DIGEST_LENGTH])]);
+        assert!(!node.validate_vertex(&unknown, 5, 1));
+        // A known vertex, but not of the previous round.
+        let skipping = vertex::<DenseEdges>(&keypairs, 1, 5, vec![node.dag.get(3, 0).unwrap().hash]);
+        assert!(!node.validate_vertex(&skipping, 5, 1));
+    }
+
+    #[tokio::test]
+    async fn fetches_a_ready_body_from_the_ready_senders() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        let (tx, mut rx) = mpsc::channel(64);
+        let v = vertex::<DenseEdges>(&keypairs, 3, 1, vec![GENESIS_HASH]);
+
+        // f+1 READYs make us send ours, completing the 2f+1 quorum without the VAL.
+        node.handle_rbc_ready(1, v.hash, &tx).await.unwrap();
+        node.handle_rbc_ready(2, v.hash, &tx).await.unwrap();
+        let mut requests = Vec::new();
+        while let Ok(message) = rx.try_recv() {
+            if let (Recipient::Node(peer), SparseMessage::SyncRequest(request)) = message {
+                requests.push((peer, request.hashes));
+            }
+        }
+        assert_eq!(requests.len(), 1);
+        let (peer, hashes) = &requests[0];
+        assert!([1, 2].contains(peer), "asked Node {} which sent no READY", peer);
+        assert_eq!(hashes, &[v.hash]);
+        assert!(!node.dag.vertices.contains_key(&v.hash));
+
+        node.handle_sync_response(*peer, vec![v.clone()], &tx).await.unwrap();
+        assert!(node.delivered_vertices.contains(&v.hash));
+        assert!(node.dag.vertices.contains_key(&v.hash));
+        assert!(!node.ready_counts.contains_key(&v.hash));
+    }
+}
@@ -1,44 +1,73 @@
-use super::sparse_bullshark::SparseBullshark;
-use crate::types::vertex::Vertex;
-use log::{info, warn,debug};
+use super::node::DagNode;
+use super::policy::{CommitRule, EdgePolicy};
+use crate::storage::Record;
+use crate::types::{committed::CommittedSubDag, vertex::Vertex};
+use tokio::sync::mpsc::{self, Receiver};
+use log::{debug, warn};
 use std::collections::HashSet;
-impl SparseBullshark {
+use std::io;
+use std::sync::Arc;
 
-    // ✅ Add `pub` to make this function visible to other files in the module.
-    pub fn try_committing(&mut self, committed_vertex: Vertex) {
+/// The state machine is snapshotted once this many rounds were ordered
+/// since the last snapshot; in between only the ordered round is logged.
+pub const SNAPSHOT_INTERVAL: u64 = 100;
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+
+    /// Commits the anchor two rounds below `committed_vertex` once enough of
+    /// the round above it link to it, ordering it and the anchors it commits.
+    pub fn try_committing(&mut self, committed_vertex: &Vertex) -> io::Result<()> {
         if committed_vertex.round < 2 {
-            return;
+            return Ok(());
         }
 
         let anchor_round = committed_vertex.round-2;
         
         let anchor = match self.get_anchor(anchor_round){
             Some(a) => a.clone(),
-            None => return,
+            None => return Ok(()),
         };
 
         if anchor.round <= self.last_ordered_round{
-            return;
+            return Ok(());
         }
         let mut vote_count = 0;
         if let Some(voters) = self.dag.get_round(anchor_round+1){
-            for voter in voters{
+            for voter in voters.values(){
                 if voter.edges.contains(&anchor.hash){
                     vote_count+=1;
                 }
             }
         }
-        let direct_commit_threshold = 2*self.f+1;
+        let direct_commit_threshold = self.commit_rule.direct_commit_threshold(self.f);
         if vote_count >= direct_commit_threshold{
             debug!(
                 "[Node {}] DIRECT COMMIT of anchor in round {}",
                 self.environment.my_node.id, anchor.round
             );
-            self.order_anchors(anchor);
+            self.order_anchors(anchor)?;
         }
+        Ok(())
     }
 
-    pub fn order_anchors(&mut self, anchor: Vertex) {
+    pub fn order_anchors(&mut self, anchor: Arc<Vertex>) -> io::Result<()> {
+        let new_ordered_round = self.order_up_to(anchor);
+        // Written once the blocks were executed, so a snapshot matches the
+        // ordered prefix; a crash before this re-executes them after recovery.
+        if new_ordered_round >= self.last_snapshot_round + SNAPSHOT_INTERVAL {
+            let state = self.state_machine.snapshot();
+            self.persist(&Record::Snapshot { round: new_ordered_round, state })?;
+            self.last_snapshot_round = new_ordered_round;
+        } else {
+            self.persist(&Record::LastOrderedRound { round: new_ordered_round })?;
+        }
+        self.garbage_collect();
+        Ok(())
+    }
+
+    /// Orders `anchor` and the earlier anchors it commits indirectly, and
+    /// executes their history. Returns the new last ordered round.
+    pub fn order_up_to(&mut self, anchor: Arc<Vertex>) -> u64 {
         self.ordered_anchors_stack.push(anchor.clone());
         let mut current_anchor = anchor;
         let mut r = current_anchor.round-2;
@@ -56,18 +85,23 @@ impl SparseBullshark {
             r-=2;
         }
         
-        let new_ordered_round = self.ordered_anchors_stack.last().unwrap().round;
-        // 2. NOW, with no other borrows active, you are free to mutate self.
+        // The stack ends with the oldest anchor; we ordered up to the newest.
+        let new_ordered_round = self.ordered_anchors_stack[0].round;
         self.last_ordered_round = new_ordered_round;    
         self.order_history();
+        new_ordered_round
     }
 
     pub fn order_history(&mut self) {
         while let Some(anchor) = self.ordered_anchors_stack.pop() {
+            // Only the history within gc_depth rounds of the anchor is ordered.
+            // This depends on the anchor alone, so every node orders the same
+            // vertices however its own garbage collection is lagging.
+            let horizon = anchor.round.saturating_sub(self.config.gc_depth);
             // Use a queue for a breadth-first traversal of the anchor's causal past.
             let mut to_order_queue = vec![anchor.clone()];
             let mut to_order_set = HashSet::new();
-            to_order_set.insert(anchor.hash.clone());
+            to_order_set.insert(anchor.hash);
 
             let mut head = 0;
             while head < to_order_queue.len() {
@@ -78,8 +112,8 @@ impl SparseBullshark {
                 }
                 for parent_hash in &current.edges {
                     if !to_order_set.contains(parent_hash) {
-                        if let Some(parent) = self.dag.vertices.get(parent_hash) {
-                            to_order_set.insert(parent.hash.clone());
+                        if let Some(parent) = self.dag.vertices.get(parent_hash).filter(|parent| parent.round >= horizon) {
+                            to_order_set.insert(parent.hash);
                             to_order_queue.push(parent.clone());
                         }
                     }
@@ -88,20 +122,123 @@ impl SparseBullshark {
             
             // For deterministic ordering, sort the vertices to be ordered.
             // A simple sort by hash is a good deterministic rule.
-            to_order_queue.sort_by(|a, b| a.hash.cmp(&b.hash));
+            to_order_queue.sort_by_key(|a| a.hash);
 
+            let mut vertices = Vec::new();
             for vertex in to_order_queue {
                 if !self.already_ordered.contains(&vertex.hash) {
                     debug!(
                         "[Node {}] FINALIZING AND ORDERING Vertex from Node {} in round {}",
                         self.environment.my_node.id, vertex.source, vertex.round
                     );
-                    // This is where you would deliver the block to your application.
-                    // For example: self.state_machine.execute(vertex.block);
                     self.finalized_block_count += 1;
-                    self.already_ordered.insert(vertex.hash.clone());
+                    self.already_ordered.insert(vertex.hash);
+                    vertices.push(vertex);
                 }
             }
+            self.deliver(anchor, vertices);
+        }
+    }
+
+    /// Executes a committed sub-DAG and queues it for the application, if
+    /// one subscribed (see `send_committed`).
+    fn deliver(&mut self, anchor: Arc<Vertex>, vertices: Vec<Arc<Vertex>>) {
+        for vertex in &vertices {
+            if let Err(e) = self.state_machine.execute(&vertex.block) {
+                warn!("[Node {}] Skipping block of Node {} in round {}: {}", self.environment.my_node.id, vertex.source, vertex.round, e);
+            }
+        }
+        if self.committed_tx.is_none() {
+            return;
+        }
+        self.unsent_sub_dags.push(CommittedSubDag {
+            anchor: anchor.hash,
+            leader: anchor.source,
+            round: anchor.round,
+            blocks: vertices.iter().map(|v| v.block.clone()).collect(),
+            vertices,
+            state_root: self.state_machine.state_root(),
+        });
+    }
+
+    /// Sends the sub-DAGs committed since the last call to the subscriber,
+    /// waiting for room in its channel.
+    pub async fn send_committed(&mut self) {
+        for sub_dag in std::mem::take(&mut self.unsent_sub_dags) {
+            let Some(committed_tx) = &self.committed_tx else { return };
+            if committed_tx.send(sub_dag).await.is_err() {
+                // The application went away; stop building sub-DAGs for it.
+                self.committed_tx = None;
+            }
+        }
+    }
+
+    /// Returns the stream of committed sub-DAGs, one per ordered anchor, in
+    /// commit order. Only the latest subscriber receives them.
+    ///
+    /// The channel holds `limits.commit_channel_size` sub-DAGs. Once it is
+    /// full the node waits for the application before handling any further
+    /// message, so a slow application slows consensus down rather than
+    /// piling sub-DAGs up in memory.
+    pub fn subscribe(&mut self) -> Receiver<CommittedSubDag> {
+        let (committed_tx, committed_rx) = mpsc::channel(self.config.limits.commit_channel_size);
+        self.committed_tx = Some(committed_tx);
+        committed_rx
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Limits, NodeConfig};
+    use crate::consensus::testing::{dense_node, dense_rounds};
+
+    fn config(commit_channel_size: usize) -> NodeConfig {
+        NodeConfig { limits: Limits { commit_channel_size, ..Limits::default() }, ..NodeConfig::default() }
+    }
+
+    #[tokio::test]
+    async fn sends_every_sub_dag_in_commit_order_through_a_full_channel() {
+        let (mut node, keypairs) = dense_node(4, 0, config(1));
+        let mut committed = node.subscribe();
+        for vertex in dense_rounds(&node, &keypairs, 12) {
+            node.accept_vertex(vertex).unwrap();
+        }
+        let queued = node.unsent_sub_dags.len();
+        assert!(queued > 1);
+
+        let application = tokio::spawn(async move {
+            let mut rounds = Vec::new();
+            while let Some(sub_dag) = committed.recv().await {
+                rounds.push(sub_dag.round);
+            }
+            rounds
+        });
+        // Only returns once the application took all but the last one.
+        node.send_committed().await;
+        assert!(node.unsent_sub_dags.is_empty());
+        drop(node);
+
+        let rounds = application.await.unwrap();
+        assert_eq!(rounds.len(), queued);
+        assert!(rounds.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", rounds);
+    }
+
+    #[tokio::test]
+    async fn stops_building_sub_dags_once_the_application_is_gone() {
+        let (mut node, keypairs) = dense_node(4, 0, config(16));
+        drop(node.subscribe());
+        let mut vertices = dense_rounds(&node, &keypairs, 12).into_iter();
+        for vertex in vertices.by_ref().take(24) {
+            node.accept_vertex(vertex).unwrap();
+        }
+        node.send_committed().await;
+        assert!(node.committed_tx.is_none());
+
+        let ordered = node.last_ordered_round;
+        for vertex in vertices {
+            node.accept_vertex(vertex).unwrap();
         }
+        assert!(node.last_ordered_round > ordered);
+        assert!(node.unsent_sub_dags.is_empty());
     }
-}
\ No newline at end of file
+}
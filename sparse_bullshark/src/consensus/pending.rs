@@ -0,0 +1,234 @@
+use std::collections::{HashMap, HashSet};
+use crate::network::message::VertexMessage;
+use crate::types::vertex::{NodeId, Vertex, VertexHash};
+
+struct PendingVertex {
+    sender: NodeId,
+    message: VertexMessage,
+    /// Parents we still don't hold.
+    missing: HashSet<VertexHash>,
+}
+
+/// Delivered vertices waiting for some of their parents, indexed by the
+/// parents they wait for. Inserting a parent into the DAG releases exactly
+/// the vertices it was the last missing parent of.
+pub struct PendingVertices {
+    vertices: HashMap<VertexHash, PendingVertex>,
+    waiting_on: HashMap<VertexHash, Vec<VertexHash>>,
+    per_sender: HashMap<NodeId, usize>,
+    pub max_per_sender: usize,
+}
+
+impl PendingVertices {
+    pub fn new(max_per_sender: usize) -> Self {
+        PendingVertices {
+            vertices: HashMap::new(),
+            waiting_on: HashMap::new(),
+            per_sender: HashMap::new(),
+            max_per_sender,
+        }
+    }
+
+    /// Buffers `message` until all of `missing` are in the DAG. Returns false
+    /// if it was already buffered or its sender is over its budget.
+    pub fn insert(&mut self, sender: NodeId, message: VertexMessage, missing: Vec<VertexHash>) -> bool {
+        let hash = message.vertex.hash;
+        if self.vertices.contains_key(&hash) {
+            return false;
+        }
+        let buffered = self.per_sender.entry(sender).or_default();
+        if *buffered >= self.max_per_sender {
+            return false;
+        }
+        *buffered += 1;
+
+        let missing: HashSet<VertexHash> = missing.into_iter().collect();
+        for parent in &missing {
+            self.waiting_on.entry(*parent).or_default().push(hash);
+        }
+        self.vertices.insert(hash, PendingVertex { sender, message, missing });
+        true
+    }
+
+    /// Marks `parent` as available and returns the vertices that no longer
+    /// miss any parent, removing them from the buffer.
+    pub fn resolve(&mut self, parent: &VertexHash) -> Vec<(NodeId, VertexMessage)> {
+        let mut ready = Vec::new();
+        for child in self.waiting_on.remove(parent).unwrap_or_default() {
+            let Some(pending) = self.vertices.get_mut(&child) else { continue };
+            pending.missing.remove(parent);
+            if pending.missing.is_empty() {
+                if let Some(pending) = self.vertices.remove(&child) {
+                    if let Some(buffered) = self.per_sender.get_mut(&pending.sender) {
+                        *buffered -= 1;
+                    }
+                    ready.push((pending.sender, pending.message));
+                }
+            }
+        }
+        ready
+    }
+
+    /// Drops every buffered vertex that waits, directly or through other
+    /// buffered vertices, on `parent`, which turned out to be invalid: none
+    /// of them can ever be released. Returns how many were dropped.
+    pub fn reject(&mut self, parent: &VertexHash) -> usize {
+        let mut rejected = vec![*parent];
+        let mut dropped = 0;
+        while let Some(parent) = rejected.pop() {
+            for child in self.waiting_on.remove(&parent).unwrap_or_default() {
+                let Some(pending) = self.vertices.remove(&child) else { continue };
+                if let Some(buffered) = self.per_sender.get_mut(&pending.sender) {
+                    *buffered -= 1;
+                }
+                // Forget it among the waiters of its other missing parents.
+                for other in pending.missing.iter().filter(|other| **other != parent) {
+                    if let Some(children) = self.waiting_on.get_mut(other) {
+                        children.retain(|waiting| *waiting != child);
+                        if children.is_empty() {
+                            self.waiting_on.remove(other);
+                        }
+                    }
+                }
+                rejected.push(child);
+                dropped += 1;
+            }
+        }
+        dropped
+    }
+
+    /// Drops the buffered vertices below `round`; their parents were
+    /// garbage collected, so they won't be released anymore.
+    pub fn prune_below(&mut self, round: u64) {
+        let pruned: Vec<VertexHash> = self.vertices.iter()
+            .filter(|(_, pending)| pending.message.vertex.round < round)
+            .map(|(hash, _)| *hash)
+            .collect();
+        if pruned.is_empty() {
+            return;
+        }
+        for hash in pruned {
+            if let Some(pending) = self.vertices.remove(&hash) {
+                if let Some(buffered) = self.per_sender.get_mut(&pending.sender) {
+                    *buffered -= 1;
+                }
+            }
+        }
+        let vertices = &self.vertices;
+        self.waiting_on.retain(|_, children| {
+            children.retain(|child| vertices.contains_key(child));
+            !children.is_empty()
+        });
+    }
+
+    pub fn contains(&self, hash: &VertexHash) -> bool {
+        self.vertices.contains_key(hash)
+    }
+
+    pub fn get(&self, hash: &VertexHash) -> Option<&Vertex> {
+        self.vertices.get(hash).map(|pending| &pending.message.vertex)
+    }
+
+    pub fn len(&self) -> usize {
+        self.vertices.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::digest::{Digest, DIGEST_LENGTH};
+
+    fn hash(id: u8) -> VertexHash {
+        Digest([id; DIGEST_LENGTH])
+    }
+
+    /// A message from `sender` carrying vertex `id` of `round`, which links to `parents`.
+    fn message(sender: NodeId, id: u8, round: u64, parents: &[u8]) -> VertexMessage {
+        let vertex = Vertex {
+            hash: hash(id),
+            round,
+            source: sender,
+            block: vec![],
+            edges: parents.iter().map(|parent| hash(*parent)).collect(),
+            signed_round: vec![],
+            sample_proof: vec![],
+            signature: vec![],
+        };
+        VertexMessage { sender, vertex }
+    }
+
+    fn insert(pending: &mut PendingVertices, sender: NodeId, id: u8, round: u64, parents: &[u8]) -> bool {
+        pending.insert(sender, message(sender, id, round, parents), parents.iter().map(|parent| hash(*parent)).collect())
+    }
+
+    fn released(ready: Vec<(NodeId, VertexMessage)>) -> Vec<u8> {
+        let mut ids: Vec<u8> = ready.into_iter().map(|(_, message)| message.vertex.hash.0[0]).collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    #[test]
+    fn buffers_a_vertex_once() {
+        let mut pending = PendingVertices::new(10);
+        assert!(insert(&mut pending, 0, 10, 2, &[1]));
+        assert!(!insert(&mut pending, 0, 10, 2, &[1]));
+        assert!(pending.contains(&hash(10)));
+        assert_eq!(pending.get(&hash(10)).map(|vertex| vertex.round), Some(2));
+        assert_eq!(pending.len(), 1);
+    }
+
+    #[test]
+    fn releases_a_vertex_with_its_last_missing_parent() {
+        let mut pending = PendingVertices::new(10);
+        insert(&mut pending, 0, 10, 2, &[1, 2]);
+        insert(&mut pending, 1, 11, 2, &[2]);
+        assert!(released(pending.resolve(&hash(1))).is_empty());
+        assert_eq!(released(pending.resolve(&hash(2))), [10, 11]);
+        assert_eq!(pending.len(), 0);
+        assert!(pending.resolve(&hash(2)).is_empty());
+    }
+
+    #[test]
+    fn bounds_the_vertices_of_each_sender() {
+        let mut pending = PendingVertices::new(2);
+        assert!(insert(&mut pending, 0, 10, 2, &[1]));
+        assert!(insert(&mut pending, 0, 11, 2, &[1]));
+        assert!(!insert(&mut pending, 0, 12, 2, &[1]));
+        // Other senders have their own budget.
+        assert!(insert(&mut pending, 1, 13, 2, &[1]));
+
+        // Released vertices free their sender's budget.
+        assert_eq!(released(pending.resolve(&hash(1))), [10, 11, 13]);
+        assert!(insert(&mut pending, 0, 12, 2, &[1]));
+    }
+
+    #[test]
+    fn prunes_the_rounds_below() {
+        let mut pending = PendingVertices::new(2);
+        insert(&mut pending, 0, 10, 2, &[1]);
+        insert(&mut pending, 0, 11, 3, &[1]);
+        pending.prune_below(3);
+        assert!(!pending.contains(&hash(10)));
+        assert!(pending.contains(&hash(11)));
+        assert!(insert(&mut pending, 0, 12, 3, &[1]));
+        assert_eq!(released(pending.resolve(&hash(1))), [11, 12]);
+    }
+
+    #[test]
+    fn rejects_the_vertices_waiting_on_an_invalid_one() {
+        let mut pending = PendingVertices::new(2);
+        // 12 waits on 11, which waits on the rejected 10; 13 doesn't depend on 10.
+        insert(&mut pending, 0, 11, 3, &[10]);
+        insert(&mut pending, 0, 12, 4, &[11, 1]);
+        insert(&mut pending, 1, 13, 4, &[1]);
+        assert_eq!(pending.reject(&hash(10)), 2);
+        assert!(!pending.contains(&hash(11)));
+        assert!(!pending.contains(&hash(12)));
+        assert_eq!(released(pending.resolve(&hash(1))), [13]);
+
+        // The rejected vertices no longer count against their sender.
+        assert!(insert(&mut pending, 0, 14, 4, &[2]));
+        assert!(insert(&mut pending, 0, 15, 4, &[2]));
+    }
+}
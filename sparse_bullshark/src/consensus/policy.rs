@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::error::Error;
+use ed25519_dalek::{Keypair, PublicKey};
+use crate::{consensus::dag::DAG, crypto::hashing::Variant, types::vertex::{NodeId, Vertex, VertexHash}};
+
+/// The variant-specific part of a new vertex: which parents it links to and
+/// whatever material receivers need to check that choice.
+pub struct EdgeSelection {
+    pub parents: Vec<VertexHash>,
+    pub signed_round: Vec<u8>,
+    pub sample_proof: Vec<u8>,
+}
+
+/// Decides how a vertex picks its edges into the previous round and how a
+/// receiver checks them. The anchor edge is added by the node itself.
+pub trait EdgePolicy {
+    /// Tags the hashes of this protocol's vertices.
+    const VARIANT: Variant;
+
+    /// Picks the parents of `source`'s vertex for `round` out of the vertices
+    /// we hold for `round - 1`.
+    fn select_parents(&self, round: u64, source: NodeId, candidates: &[Arc<Vertex>], private_key: &Keypair) -> EdgeSelection;
+
+    /// Checks the edges and proof material of a received vertex against our
+    /// DAG. `anchor` is the anchor of `vertex.round - 1`, if we hold it.
+    /// Parent availability and the hash are checked by the node.
+    fn validate_parents(
+        &self,
+        vertex: &Vertex,
+        dag: &DAG,
+        anchor: Option<&Vertex>,
+        public_keys: &HashMap<NodeId, PublicKey>,
+    ) -> Result<(), Box<dyn Error>>;
+}
+
+/// Decides how many votes in round `r + 1` an anchor of round `r` needs to be
+/// committed directly.
+pub trait CommitRule {
+    fn direct_commit_threshold(&self, f: usize) -> usize;
+}
+
+/// Direct commit with f+1 votes, as in standard Bullshark where every vertex
+/// links to all of the previous round.
+pub struct WeakQuorum;
+
+impl CommitRule for WeakQuorum {
+    fn direct_commit_threshold(&self, f: usize) -> usize {
+        f + 1
+    }
+}
+
+/// Direct commit with 2f+1 votes. Needed when vertices only link to a sample
+/// of the previous round, so f+1 votes no longer guarantee intersection.
+pub struct StrongQuorum;
+
+impl CommitRule for StrongQuorum {
+    fn direct_commit_threshold(&self, f: usize) -> usize {
+        2 * f + 1
+    }
+}
@@ -0,0 +1,223 @@
+use super::node::DagNode;
+use super::policy::{CommitRule, EdgePolicy};
+use crate::network::message::{Outgoing, Recipient, SparseMessage, VertexMessage};
+use crate::storage::Record;
+use crate::types::vertex::Vertex;
+use log::{error, info};
+use std::io;
+use tokio::sync::mpsc::Sender;
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+    /// Rebuilds the node from its storage after a restart: the DAG, the
+    /// rounds we already proposed for, our RBC votes, the ordered prefix and
+    /// the state machine it left. The state machine is restored from the
+    /// last snapshot, and the anchors ordered after it are ordered again.
+    /// Catching up with what happened while we were down is left to sync,
+    /// which only works while peers still hold those rounds (`gc_depth`).
+    pub fn recover(&mut self) -> io::Result<()> {
+        let records = self.storage.load()?;
+        if records.is_empty() {
+            return Ok(());
+        }
+        let my_id = self.environment.my_node.id;
+        let mut snapshot = None;
+        let mut ordered_rounds = Vec::new();
+        for record in records {
+            match record {
+                Record::Vertex(vertex) => self.restore_vertex(vertex),
+                Record::Proposal(vertex) => {
+                    self.last_proposed_round = self.last_proposed_round.max(vertex.round);
+                    self.restore_vertex(vertex);
+                }
+                Record::RbcEcho { hash, round } => {
+                    self.echo_counts.entry(hash).or_default().insert(my_id);
+                    self.rbc_seen.entry(hash).or_insert(round);
+                }
+                Record::RbcReady { hash, round } => {
+                    self.ready_counts.entry(hash).or_default().insert(my_id);
+                    self.rbc_seen.entry(hash).or_insert(round);
+                }
+                Record::RbcDelivered { hash, round } => {
+                    self.echo_counts.remove(&hash);
+                    self.ready_counts.remove(&hash);
+                    self.delivered_vertices.insert(hash);
+                    self.rbc_seen.entry(hash).or_insert(round);
+                }
+                Record::LastOrderedRound { round } => ordered_rounds.push(round),
+                Record::Snapshot { round, state } => {
+                    if round >= self.last_snapshot_round {
+                        self.last_snapshot_round = round;
+                        snapshot = Some(state);
+                    }
+                }
+            }
+        }
+        if let Some(state) = snapshot {
+            self.state_machine.restore(&state)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        }
+
+        // Never propose again for a round we already signed a vertex for.
+        self.round = self.round.max(self.last_proposed_round + 1);
+        self.last_ordered_round = self.last_snapshot_round;
+        self.restore_ordered_prefix();
+        self.replay_ordered_rounds(ordered_rounds);
+        self.garbage_collect();
+        info!(
+            "[Node {}] Recovered {} vertices, resuming at round {} with anchors ordered up to round {}",
+            my_id, self.dag.vertices.len(), self.round, self.last_ordered_round
+        );
+        Ok(())
+    }
+
+    fn restore_vertex(&mut self, vertex: Vertex) {
+        let (hash, round) = (vertex.hash, vertex.round);
+        if let Ok(true) = self.dag.insert(vertex) {
+            self.delivered_vertices.insert(hash);
+            self.rbc_seen.entry(hash).or_insert(round);
+        }
+    }
+
+    /// Orders the anchors committed after the snapshot again, executing
+    /// their blocks, in the order they were committed before the restart.
+    fn replay_ordered_rounds(&mut self, mut rounds: Vec<u64>) {
+        rounds.sort_unstable();
+        for round in rounds {
+            if round <= self.last_ordered_round {
+                continue;
+            }
+            let Some(anchor) = self.get_anchor(round).cloned() else {
+                error!("[Node {}] Anchor of the ordered round {} is missing from storage", self.environment.my_node.id, round);
+                return;
+            };
+            self.order_up_to(anchor);
+        }
+    }
+
+    /// Every committed anchor is in the causal history of the last one, and
+    /// so is everything they ordered: marking that history as ordered keeps
+    /// later anchors from ordering any of it a second time.
+    fn restore_ordered_prefix(&mut self) {
+        let Some(anchor) = self.get_anchor(self.last_ordered_round).cloned() else {
+            if self.last_ordered_round > 0 {
+                error!("[Node {}] Anchor of the last ordered round {} is missing from storage", self.environment.my_node.id, self.last_ordered_round);
+            }
+            return;
+        };
+        let mut stack = vec![anchor];
+        while let Some(vertex) = stack.pop() {
+            if !self.already_ordered.insert(vertex.hash) {
+                continue;
+            }
+            for parent_hash in &vertex.edges {
+                if let Some(parent) = self.dag.vertices.get(parent_hash) {
+                    stack.push(parent.clone());
+                }
+            }
+        }
+    }
+
+    /// Called once we are connected again after a recovery. Our last vertex
+    /// may not have reached everyone before the crash, so it is sent again;
+    /// it's the same vertex, so this is not an equivocation.
+    pub async fn resume(&mut self, dispatcher_tx: &Sender<Outgoing>) {
+        let my_id = self.environment.my_node.id;
+        let last_proposal = self.dag.get(self.last_proposed_round, my_id).map(|vertex| vertex.as_ref().clone());
+        if let Some(vertex) = last_proposal {
+            let message = SparseMessage::Vertex(VertexMessage { sender: my_id, vertex });
+            if dispatcher_tx.send((Recipient::All, message)).await.is_err() {
+                error!("[Node {}] Failed to send vertex to dispatcher.", my_id);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+    use crate::config::NodeConfig;
+    use crate::consensus::bullshark::{Bullshark, DenseEdges};
+    use crate::consensus::testing::{dense_node, dense_rounds, members, vertex};
+    use crate::execution::kv::KvStore;
+    use crate::storage::{wal::Wal, Record};
+
+    /// Enough rounds for two snapshots and a compaction of the log.
+    const ROUNDS: u64 = 230;
+
+    /// A path for a fresh log in the temp directory.
+    fn wal_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sparse-bullshark-recovery-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    /// Node 0 of four, logging to `path` and executing into a key-value store.
+    fn node(path: &PathBuf) -> Bullshark {
+        let (mut node, _) = dense_node(4, 0, NodeConfig::default());
+        node.state_machine = Box::new(KvStore::default());
+        node.storage = Box::new(Wal::open(path).unwrap());
+        node
+    }
+
+    /// Runs `node` through `rounds` full rounds as if they were delivered,
+    /// proposing its own vertices the way `process_work_loop` does.
+    fn run(node: &mut Bullshark, rounds: u64) {
+        let (keypairs, _) = members(4);
+        for vertex in dense_rounds(node, &keypairs, rounds) {
+            if vertex.source == 0 {
+                node.persist(&Record::Proposal(vertex.clone())).unwrap();
+                node.last_proposed_round = vertex.round;
+                node.round = vertex.round + 1;
+            }
+            node.accept_vertex(vertex).unwrap();
+        }
+    }
+
+    #[test]
+    fn replays_the_anchors_ordered_since_the_snapshot() {
+        let path = wal_path("replay");
+        let mut before = node(&path);
+        run(&mut before, ROUNDS);
+        assert!(before.last_snapshot_round > 0);
+        assert!(before.last_ordered_round > before.last_snapshot_round);
+        let (ordered, snapshot, root) = (before.last_ordered_round, before.last_snapshot_round, before.state_machine.state_root());
+        drop(before);
+
+        let mut after = node(&path);
+        after.recover().unwrap();
+        assert_eq!(after.last_snapshot_round, snapshot);
+        assert_eq!(after.last_ordered_round, ordered);
+        assert_eq!(after.state_machine.state_root(), root);
+        assert_eq!((after.last_proposed_round, after.round), (ROUNDS, ROUNDS + 1));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn never_proposes_again_for_a_signed_round() {
+        let path = wal_path("proposal");
+        let mut before = node(&path);
+        run(&mut before, 6);
+        // Our vertex for round 7 was signed, but nobody else's arrived.
+        let (keypairs, _) = members(4);
+        let previous = before.dag.get_round(6).unwrap().values().map(|v| v.hash).collect();
+        let proposal = vertex::<DenseEdges>(&keypairs, 0, 7, previous);
+        before.persist(&Record::Proposal(proposal.clone())).unwrap();
+        drop(before);
+
+        let mut after = node(&path);
+        after.recover().unwrap();
+        assert_eq!(after.last_proposed_round, 7);
+        assert_eq!(after.round, 8);
+        assert_eq!(after.dag.get(7, 0).map(|v| v.hash), Some(proposal.hash));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn recovers_nothing_from_an_empty_log() {
+        let path = wal_path("empty");
+        let mut node = node(&path);
+        node.recover().unwrap();
+        assert_eq!((node.round, node.last_proposed_round, node.last_ordered_round), (1, 0, 0));
+        std::fs::remove_file(&path).unwrap();
+    }
+}
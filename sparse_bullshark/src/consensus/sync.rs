@@ -0,0 +1,231 @@
+use super::node::DagNode;
+use super::policy::{CommitRule, EdgePolicy};
+use crate::network::message::{Outgoing, SparseMessage, SyncRequestMessage, SyncResponseMessage, VertexMessage};
+use crate::storage::Record;
+use crate::types::vertex::{NodeId, Vertex, VertexHash};
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::io;
+use tokio::sync::mpsc::Sender;
+use tokio::time::Instant;
+
+/// A vertex we know the hash of but don't hold: a parent referenced by a
+/// buffered vertex, or an RBC body whose READY quorum we already saw.
+pub struct MissingVertex {
+    /// Peers that should hold it, asked in turn.
+    peers: Vec<NodeId>,
+    /// How many requests were sent so far.
+    attempts: usize,
+    /// When to send the next request.
+    due: Instant,
+    /// Our round when we started looking for it, for garbage collection.
+    since: u64,
+}
+
+impl<E: EdgePolicy, C: CommitRule> DagNode<E, C> {
+    /// Edges of `v` pointing to vertices we don't hold.
+    /// Parents in a garbage collected round count as present.
+    pub fn missing_parents(&self, v: &Vertex) -> Vec<VertexHash> {
+        if self.dag.is_pruned(v.round.saturating_sub(1)) {
+            return vec![];
+        }
+        v.edges.iter().filter(|hash| !self.dag.vertices.contains_key(*hash)).cloned().collect()
+    }
+
+    /// Starts tracking parents we are missing. They usually arrive through
+    /// RBC shortly after, so unless `now` is set they are only requested once
+    /// `timeouts.sync_retry_ms` has passed without them: first from `source`, whose
+    /// vertex referenced them, then from every other peer in turn.
+    pub fn track_missing_parents(&mut self, source: NodeId, missing: Vec<VertexHash>, now: bool) {
+        let due = if now { Instant::now() } else { Instant::now() + self.config.timeouts.sync_retry() };
+        let my_id = self.environment.my_node.id;
+        let mut peers = vec![source];
+        peers.extend(self.environment.nodes.iter().map(|node| node.id).filter(|id| *id != source && *id != my_id));
+        for hash in missing {
+            self.sync_requests.entry(hash).or_insert_with(|| MissingVertex {
+                peers: peers.clone(),
+                attempts: 0,
+                due,
+                since: self.round,
+            });
+        }
+    }
+
+    /// Pulls the body of a vertex that reached its READY quorum before we got
+    /// its VAL, asking the READY senders in turn. At least f+1 of them are
+    /// correct, and a correct node only sends READY for a body it can serve
+    /// or one that 2f+1 others echoed.
+    pub async fn fetch_rbc_body(&mut self, hash: VertexHash, dispatcher_tx: &Sender<Outgoing>) {
+        if self.sync_requests.contains_key(&hash) {
+            return;
+        }
+        let my_id = self.environment.my_node.id;
+        let peers: Vec<NodeId> = self.ready_counts.get(&hash)
+            .map(|voters| voters.iter().copied().filter(|id| *id != my_id).collect())
+            .unwrap_or_default();
+        if peers.is_empty() {
+            return;
+        }
+        debug!("[Node {}] RBC ready to deliver but missing vertex body, fetching it", my_id);
+        self.sync_requests.insert(hash, MissingVertex { peers, attempts: 0, due: Instant::now(), since: self.round });
+        self.retry_sync_requests(dispatcher_tx).await;
+    }
+
+    /// Gives up on vertices we started looking for before `round`.
+    pub fn prune_sync_requests(&mut self, round: u64) {
+        self.sync_requests.retain(|_, missing| missing.since >= round);
+    }
+
+    /// Requests every missing vertex that is due, each from the next peer on its list.
+    pub async fn retry_sync_requests(&mut self, dispatcher_tx: &Sender<Outgoing>) {
+        let dag = &self.dag;
+        self.sync_requests.retain(|hash, _| !dag.vertices.contains_key(hash));
+
+        let my_id = self.environment.my_node.id;
+        let now = Instant::now();
+        let mut by_peer: HashMap<NodeId, Vec<VertexHash>> = HashMap::new();
+        for (hash, missing) in self.sync_requests.iter_mut() {
+            if now < missing.due {
+                continue;
+            }
+            let peer = missing.peers[missing.attempts % missing.peers.len()];
+            missing.attempts += 1;
+            missing.due = now + self.config.timeouts.sync_retry();
+            by_peer.entry(peer).or_default().push(*hash);
+        }
+
+        for (peer, hashes) in by_peer {
+            for chunk in hashes.chunks(self.config.limits.max_sync_batch) {
+                debug!("[Node {}] Requesting {} missing vertices from Node {}", my_id, chunk.len(), peer);
+                let request = SparseMessage::SyncRequest(SyncRequestMessage { hashes: chunk.to_vec() });
+                self.send_to(peer, request, dispatcher_tx).await;
+            }
+        }
+    }
+
+    /// Looks a vertex up wherever we may hold it: in the DAG, as an RBC body
+    /// not yet delivered, or delivered but buffered.
+    fn find_vertex(&self, hash: &VertexHash) -> Option<&Vertex> {
+        self.dag.vertices.get(hash).map(|vertex| vertex.as_ref())
+            .or_else(|| self.pending_rbc_vertices.get(hash))
+            .or_else(|| self.pending_vertices.get(hash))
+    }
+
+    /// Answers with the requested vertices we hold.
+    pub async fn handle_sync_request(&mut self, sender: NodeId, hashes: Vec<VertexHash>, dispatcher_tx: &Sender<Outgoing>) {
+        let vertices: Vec<Vertex> = hashes.iter()
+            .take(self.config.limits.max_sync_batch)
+            .filter_map(|hash| self.find_vertex(hash).cloned())
+            .collect();
+        if vertices.is_empty() {
+            return;
+        }
+        let response = SparseMessage::SyncResponse(SyncResponseMessage { vertices });
+        self.send_to(sender, response, dispatcher_tx).await;
+    }
+
+    /// Feeds fetched vertices into the DAG. A vertex is only accepted if we
+    /// asked for it and it hashes to the requested hash, signed by its source:
+    /// either its child or an RBC READY quorum committed to that hash, so it
+    /// counts as delivered.
+    pub async fn handle_sync_response(&mut self, sender: NodeId, vertices: Vec<Vertex>, dispatcher_tx: &Sender<Outgoing>) -> io::Result<()> {
+        for vertex in vertices {
+            if !self.sync_requests.contains_key(&vertex.hash) || !self.is_signed_by_source(&vertex) {
+                warn!("[Node {}] Ignoring unrequested or corrupt vertex synced from Node {}", self.environment.my_node.id, sender);
+                continue;
+            }
+            debug!("[Node {}] Synced vertex from Node {} in round {} via Node {}", self.environment.my_node.id, vertex.source, vertex.round, sender);
+            self.sync_requests.remove(&vertex.hash);
+            self.pending_rbc_vertices.remove(&vertex.hash);
+            self.echo_counts.remove(&vertex.hash);
+            self.ready_counts.remove(&vertex.hash);
+            self.persist(&Record::RbcDelivered { hash: vertex.hash, round: self.round })?;
+            self.delivered_vertices.insert(vertex.hash);
+            self.rbc_seen.entry(vertex.hash).or_insert(self.round);
+            let source = vertex.source;
+            self.handle_new_vertex_message(source, VertexMessage { sender: source, vertex }, dispatcher_tx).await?;
+        }
+        Ok(())
+    }
+}
+#[cfg(test)]
+mod tests {
+    use crate::config::{NodeConfig, Timeouts};
+    use crate::consensus::bullshark::DenseEdges;
+    use crate::consensus::testing::{dense_node, vertex};
+    use crate::network::message::{Outgoing, Recipient, SparseMessage};
+    use crate::types::vertex::{NodeId, VertexHash, GENESIS_HASH};
+    use tokio::sync::mpsc::{self, Receiver};
+
+    /// The peer and the hashes of the next sync request sent.
+    fn next_request(rx: &mut Receiver<Outgoing>) -> (NodeId, Vec<VertexHash>) {
+        match rx.try_recv() {
+            Ok((Recipient::Node(peer), SparseMessage::SyncRequest(request))) => (peer, request.hashes),
+            _ => panic!("expected a sync request"),
+        }
+    }
+
+    #[tokio::test]
+    async fn accepts_a_requested_vertex() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        let (tx, _rx) = mpsc::channel(64);
+        let v = vertex::<DenseEdges>(&keypairs, 1, 1, vec![GENESIS_HASH]);
+        node.track_missing_parents(1, vec![v.hash], true);
+        node.handle_sync_response(2, vec![v.clone()], &tx).await.unwrap();
+        assert!(node.dag.vertices.contains_key(&v.hash));
+        assert!(node.sync_requests.is_empty());
+    }
+
+    #[tokio::test]
+    async fn drops_a_vertex_that_was_not_requested() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        let (tx, _rx) = mpsc::channel(64);
+        let v = vertex::<DenseEdges>(&keypairs, 1, 1, vec![GENESIS_HASH]);
+        node.handle_sync_response(2, vec![v.clone()], &tx).await.unwrap();
+        assert!(!node.dag.vertices.contains_key(&v.hash));
+        assert!(!node.delivered_vertices.contains(&v.hash));
+    }
+
+    #[tokio::test]
+    async fn drops_a_vertex_not_signed_by_its_source() {
+        let (mut node, keypairs) = dense_node(4, 0, NodeConfig::default());
+        let (tx, _rx) = mpsc::channel(64);
+        let v = vertex::<DenseEdges>(&keypairs, 1, 1, vec![GENESIS_HASH]);
+        node.track_missing_parents(1, vec![v.hash], true);
+
+        let mut forged = v.clone();
+        forged.sign(&keypairs[2]);
+        let mut altered = v.clone();
+        altered.block = vec![];
+        node.handle_sync_response(2, vec![forged, altered], &tx).await.unwrap();
+        assert!(!node.dag.vertices.contains_key(&v.hash));
+        assert!(!node.delivered_vertices.contains(&v.hash));
+        // Still missing, so still requested.
+        assert!(node.sync_requests.contains_key(&v.hash));
+    }
+
+    #[tokio::test]
+    async fn retries_go_round_the_peers() {
+        let config = NodeConfig { timeouts: Timeouts { sync_retry_ms: 0, ..Timeouts::default() }, ..NodeConfig::default() };
+        let (mut node, keypairs) = dense_node(4, 0, config);
+        let (tx, mut rx) = mpsc::channel(64);
+        let v = vertex::<DenseEdges>(&keypairs, 1, 1, vec![GENESIS_HASH]);
+        node.track_missing_parents(2, vec![v.hash], true);
+
+        let mut peers = Vec::new();
+        for _ in 0..6 {
+            node.retry_sync_requests(&tx).await;
+            let (peer, hashes) = next_request(&mut rx);
+            assert_eq!(hashes, [v.hash]);
+            peers.push(peer);
+        }
+        // The source of the child first, then every other peer but us.
+        assert_eq!(peers, [2, 1, 3, 2, 1, 3]);
+
+        // Once the vertex is in, it isn't requested anymore.
+        node.handle_sync_response(2, vec![v], &tx).await.unwrap();
+        while rx.try_recv().is_ok() {}
+        node.retry_sync_requests(&tx).await;
+        assert!(rx.try_recv().is_err());
+    }
+}
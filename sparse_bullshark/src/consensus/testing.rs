@@ -0,0 +1,86 @@
+//! Committees, nodes and vertices for the consensus tests.
+
+use ed25519_dalek::Keypair;
+use rand::SeedableRng;
+use rand_chacha::ChaCha20Rng;
+use shared::domain::{committee::Committee as Members, environment::Environment, node::Node};
+use shared::domain::transaction::Transaction;
+use shared::keys::generate_keypair;
+use crate::config::NodeConfig;
+use crate::consensus::{bullshark::Bullshark, node::DagNode, policy::{CommitRule, EdgePolicy}};
+use crate::types::digest::Digest;
+use crate::types::vertex::{NodeId, Vertex, VertexHash, GENESIS_HASH};
+
+/// A committee of `n` with the keys every proof scheme needs, always the same.
+pub fn members(n: u32) -> (Vec<Keypair>, Members) {
+    let mut rng = ChaCha20Rng::seed_from_u64(0);
+    let keypairs: Vec<Keypair> = (0..n).map(|_| generate_keypair(&mut rng)).collect();
+    #[cfg_attr(not(feature = "bls"), allow(unused_mut))]
+    let mut members = Members {
+        nodes: (0..n).map(|id| Node { id, host: "127.0.0.1".to_string(), port: 9000 + id as u16, stake: 1 }).collect(),
+        public_keys: keypairs.iter().enumerate().map(|(id, kp)| (id as NodeId, kp.public)).collect(),
+        bls_public_keys: Default::default(),
+        bls_proofs_of_possession: Default::default(),
+    };
+    #[cfg(feature = "bls")]
+    for (id, kp) in keypairs.iter().enumerate() {
+        use crate::crypto::bls;
+        members.bls_public_keys.insert(id as NodeId, bls::public_key(kp).to_compressed().to_vec());
+        members.bls_proofs_of_possession.insert(id as NodeId, bls::proof_of_possession(kp).to_compressed().to_vec());
+    }
+    (keypairs, members)
+}
+
+fn environment(members: &Members, my_id: NodeId) -> Environment {
+    Environment {
+        my_node: members.nodes[my_id as usize].clone(),
+        nodes: members.nodes.clone(),
+        test_flag: false,
+        transaction_size: 16,
+        n_transactions: 1,
+    }
+}
+
+/// Node `my_id` of a Bullshark committee of `n`, with the committee's keys.
+pub fn dense_node(n: u32, my_id: NodeId, config: NodeConfig) -> (Bullshark, Vec<Keypair>) {
+    let (keypairs, members) = members(n);
+    let private_key = Keypair::from_bytes(&keypairs[my_id as usize].to_bytes()).unwrap();
+    (Bullshark::new(environment(&members, my_id), config, members, private_key), keypairs)
+}
+
+/// A block putting one key, unique to `source` and `round`.
+pub fn block(source: NodeId, round: u64) -> Vec<u8> {
+    bincode::serialize(&vec![Transaction::Put { key: format!("{}-{}", source, round), value: round.to_string() }]).unwrap()
+}
+
+/// `source`'s vertex for `round` linking to `edges`, hashed as an `E` vertex and signed.
+pub fn vertex<E: EdgePolicy>(keypairs: &[Keypair], source: NodeId, round: u64, edges: Vec<VertexHash>) -> Vertex {
+    let mut vertex = Vertex {
+        hash: Digest::default(),
+        round,
+        source,
+        block: block(source, round),
+        edges,
+        signed_round: vec![],
+        sample_proof: vec![],
+        signature: vec![],
+    };
+    vertex.hash = vertex.calculate_hash(E::VARIANT);
+    vertex.sign(&keypairs[source as usize]);
+    vertex
+}
+
+/// Every vertex of rounds `1..=rounds`, round by round and in source order,
+/// each linking to the whole previous round.
+pub fn dense_rounds<E: EdgePolicy, C: CommitRule>(node: &DagNode<E, C>, keypairs: &[Keypair], rounds: u64) -> Vec<Vertex> {
+    let mut vertices = Vec::new();
+    let mut previous = vec![GENESIS_HASH];
+    for round in 1..=rounds {
+        let current: Vec<Vertex> = node.environment.nodes.iter()
+            .map(|member| vertex::<E>(keypairs, member.id, round, previous.clone()))
+            .collect();
+        previous = current.iter().map(|v| v.hash).collect();
+        vertices.extend(current);
+    }
+    vertices
+}
@@ -0,0 +1,269 @@
+//! BLS aggregate signatures for the sample proof (`bls` feature).
+//!
+//! Round signatures live in G1 (48 bytes) and public keys in G2 (96 bytes).
+//! A sample proof is the sum of the round signatures of its signers plus a
+//! bitmap over the committee, so it is `48 + ceil(n / 8)` bytes whatever the
+//! number of signers. All signers sign the same message, which lets the proof
+//! be checked with two pairings against the sum of their public keys. So that
+//! no member can pick a key cancelling the others' (a rogue key), each
+//! `bls_public_key` in the committee file comes with a
+//! `bls_proof_of_possession`, its owner's signature over the key itself, and
+//! `load` refuses the committee unless every one of them verifies.
+//!
+//! Measured on one core, release build, with every committee member signing
+//! (Ed25519 = the default `multisig` scheme), by `benches/proofs.rs`:
+//!
+//! | n   | Ed25519 proof | BLS proof | Ed25519 validate | BLS validate |
+//! |-----|---------------|-----------|------------------|--------------|
+//! | 4   | 288 B         | 49 B      | 0.2 ms           | 2.9 ms       |
+//! | 16  | 1104 B        | 50 B      | 0.5 ms           | 3.1 ms       |
+//! | 64  | 4368 B        | 56 B      | 2.4 ms           | 4.1 ms       |
+//! | 100 | 6816 B        | 61 B      | 4.1 ms           | 4.3 ms       |
+//!
+//! Apart from its block, the proof is most of a sparse vertex, so the vertex
+//! shrinks by the same number of bytes. BLS validation is dominated by the
+//! pairing check and stays nearly flat, while Ed25519 batch verification grows
+//! linearly and catches up with it around n = 100.
+
+use std::collections::HashMap;
+use std::error::Error;
+use base64::{engine::general_purpose, Engine as _};
+use bls12_381::{
+    hash_to_curve::{ExpandMsgXmd, HashToCurve},
+    multi_miller_loop, G1Affine, G1Projective, G2Affine, G2Prepared, G2Projective, Gt, Scalar,
+};
+use ed25519_dalek::Keypair;
+use sha2::{Digest, Sha512};
+use shared::domain::committee::Committee as Members;
+use crate::{
+    crypto::multisig::{round_message, Committee, ProofError, ProofScheme},
+    types::vertex::NodeId,
+};
+
+/// Ciphersuite tags for hashing round messages and, separately, public keys
+/// for their proofs of possession to G1.
+const HASH_TO_G1_DST: &[u8] = b"BLS_SIG_BLS12381G1_XMD:SHA-256_SSWU_RO_POP_";
+const POP_DST: &[u8] = b"BLS_POP_BLS12381G1_XMD:SHA-256_SSWU_RO_POP_";
+/// Domain tag for deriving a node's BLS secret key from its Ed25519 secret key.
+const SECRET_KEY_TAG: &[u8] = b"sparse-bullshark/bls-secret-key";
+const SIGNATURE_BYTES_LENGTH: usize = 48;
+const PUBLIC_KEY_BYTES_LENGTH: usize = 96;
+
+/// The BLS secret key of a node. It is derived from the Ed25519 secret key,
+/// so nodes don't need a second private key file.
+fn secret_key(private_key: &Keypair) -> Scalar {
+    let digest = Sha512::new()
+        .chain_update(SECRET_KEY_TAG)
+        .chain_update(private_key.secret.as_bytes())
+        .finalize();
+    Scalar::from_bytes_wide(&digest.into())
+}
+
+/// The BLS public key matching `private_key`, as written to the committee file.
+pub fn public_key(private_key: &Keypair) -> G2Affine {
+    G2Affine::from(G2Affine::generator() * secret_key(private_key))
+}
+
+/// The proof of possession of `private_key`'s BLS key: a signature over the
+/// public key, as written to the committee file next to it.
+pub fn proof_of_possession(private_key: &Keypair) -> G1Affine {
+    G1Affine::from(hash_to_g1(&public_key(private_key).to_compressed(), POP_DST) * secret_key(private_key))
+}
+
+fn hash_to_g1(message: &[u8], dst: &[u8]) -> G1Affine {
+    let point = <G1Projective as HashToCurve<ExpandMsgXmd<sha2_09::Sha256>>>::hash_to_curve(message, dst);
+    G1Affine::from(point)
+}
+
+fn hash_round(epoch: u64, round: u64) -> G1Affine {
+    hash_to_g1(&round_message(epoch, round), HASH_TO_G1_DST)
+}
+
+/// Checks e(signature, g2) == e(message, public_key) with a single final
+/// exponentiation.
+fn verify(signature: &G1Affine, message: &G1Affine, public_key: &G2Affine) -> bool {
+    let generator = G2Prepared::from(-G2Affine::generator());
+    let public_key = G2Prepared::from(*public_key);
+    multi_miller_loop(&[(signature, &generator), (message, &public_key)]).final_exponentiation() == Gt::identity()
+}
+
+fn verify_possession(public_key: &G2Affine, proof: &G1Affine) -> bool {
+    verify(proof, &hash_to_g1(&public_key.to_compressed(), POP_DST), public_key)
+}
+
+fn parse_signature(bytes: &[u8]) -> Option<G1Affine> {
+    let bytes: [u8; SIGNATURE_BYTES_LENGTH] = bytes.try_into().ok()?;
+    Option::from(G1Affine::from_compressed(&bytes))
+}
+
+/// Sample proofs as one aggregate BLS signature plus a signer bitmap.
+pub struct BlsProofs {
+    public_keys: HashMap<NodeId, G2Affine>,
+    committee_size: usize,
+}
+
+impl BlsProofs {
+    pub fn new(public_keys: HashMap<NodeId, G2Affine>) -> Self {
+        let committee_size = public_keys.keys().max().map_or(0, |id| *id as usize + 1);
+        BlsProofs { public_keys, committee_size }
+    }
+
+    /// Parses the BLS public keys of every member of `members`, checks their
+    /// proofs of possession and that our own entry matches our private key.
+    pub fn load(my_id: NodeId, private_key: &Keypair, members: &Members) -> Result<Self, Box<dyn Error>> {
+        let mut public_keys = HashMap::new();
+        for node in &members.nodes {
+            let id = node.id;
+            let bytes = members.bls_public_keys.get(&id)
+                .ok_or_else(|| format!("node {} has no bls_public_key, run keygen and gen-committee with the bls feature", id))?;
+            let key = <[u8; PUBLIC_KEY_BYTES_LENGTH]>::try_from(bytes.as_slice()).ok()
+                .and_then(|bytes| Option::from(G2Affine::from_compressed(&bytes)))
+                .ok_or_else(|| format!("bls_public_key of node {} is not a compressed G2 point", id))?;
+            let proof = members.bls_proofs_of_possession.get(&id)
+                .ok_or_else(|| format!("node {} has no bls_proof_of_possession", id))?;
+            let proof = parse_signature(proof)
+                .ok_or_else(|| format!("bls_proof_of_possession of node {} is not a compressed G1 point", id))?;
+            if !verify_possession(&key, &proof) {
+                return Err(format!("bls_proof_of_possession of node {} does not match its bls_public_key", id).into());
+            }
+            public_keys.insert(id, key);
+        }
+        let own_key = public_key(private_key);
+        if public_keys.get(&my_id) != Some(&own_key) {
+            return Err(format!(
+                "bls_public_key of node {} does not match its private key (expected {})",
+                my_id,
+                general_purpose::STANDARD.encode(own_key.to_compressed())
+            ).into());
+        }
+        Ok(Self::new(public_keys))
+    }
+
+    fn bitmap_len(&self) -> usize {
+        self.committee_size.div_ceil(8)
+    }
+}
+
+impl ProofScheme for BlsProofs {
+    fn sign_round(&self, epoch: u64, round: u64, private_key: &Keypair) -> Vec<u8> {
+        let signature = hash_round(epoch, round) * secret_key(private_key);
+        G1Affine::from(signature).to_compressed().to_vec()
+    }
+
+    fn verify_round(&self, epoch: u64, round: u64, signer: NodeId, signature: &[u8], _committee: &Committee) -> Result<(), ProofError> {
+        let public_key = self.public_keys.get(&signer).ok_or(ProofError::UnknownSigner(signer))?;
+        let signature = parse_signature(signature).ok_or(ProofError::Malformed)?;
+        if !verify(&signature, &hash_round(epoch, round), public_key) {
+            return Err(ProofError::InvalidSignature);
+        }
+        Ok(())
+    }
+
+    fn aggregate(&self, partials: &[(NodeId, &[u8])]) -> Vec<u8> {
+        let mut signature = G1Projective::identity();
+        let mut bitmap = vec![0u8; self.bitmap_len()];
+        for (signer, bytes) in partials {
+            let index = *signer as usize;
+            if index >= self.committee_size {
+                continue;
+            }
+            if let Some(partial) = parse_signature(bytes) {
+                signature += partial;
+                bitmap[index / 8] |= 1 << (index % 8);
+            }
+        }
+        let mut proof = G1Affine::from(signature).to_compressed().to_vec();
+        proof.extend_from_slice(&bitmap);
+        proof
+    }
+
+    fn validate(&self, epoch: u64, round: u64, sample_proof: &[u8], committee: &Committee) -> Result<Vec<NodeId>, ProofError> {
+        if sample_proof.len() != SIGNATURE_BYTES_LENGTH + self.bitmap_len() {
+            return Err(ProofError::Malformed);
+        }
+        let (signature, bitmap) = sample_proof.split_at(SIGNATURE_BYTES_LENGTH);
+        let signature = parse_signature(signature).ok_or(ProofError::Malformed)?;
+
+        // The bitmap rules out duplicates; every set bit must be a known signer.
+        let mut signers = Vec::new();
+        let mut aggregate_key = G2Projective::identity();
+        for index in 0..bitmap.len() * 8 {
+            if bitmap[index / 8] & (1 << (index % 8)) == 0 {
+                continue;
+            }
+            let signer = index as NodeId;
+            let public_key = self.public_keys.get(&signer).ok_or(ProofError::UnknownSigner(signer))?;
+            aggregate_key += public_key;
+            signers.push(signer);
+        }
+        if signers.len() < committee.quorum {
+            return Err(ProofError::NotEnoughSigners {
+                signers: signers.len(),
+                quorum: committee.quorum,
+            });
+        }
+
+        if !verify(&signature, &hash_round(epoch, round), &G2Affine::from(aggregate_key)) {
+            return Err(ProofError::InvalidSignature);
+        }
+        Ok(signers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use shared::domain::node::Node;
+    use shared::keys::generate_keypair;
+
+    /// A committee of `n` whose BLS entries are written as keygen does.
+    fn members(n: u32) -> (Vec<Keypair>, Members) {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let keypairs: Vec<Keypair> = (0..n).map(|_| generate_keypair(&mut rng)).collect();
+        let members = Members {
+            nodes: (0..n).map(|id| Node { id, host: "127.0.0.1".to_string(), port: 9000 + id as u16, stake: 1 }).collect(),
+            public_keys: keypairs.iter().enumerate().map(|(id, kp)| (id as NodeId, kp.public)).collect(),
+            bls_public_keys: keypairs.iter().enumerate()
+                .map(|(id, kp)| (id as NodeId, public_key(kp).to_compressed().to_vec()))
+                .collect(),
+            bls_proofs_of_possession: keypairs.iter().enumerate()
+                .map(|(id, kp)| (id as NodeId, proof_of_possession(kp).to_compressed().to_vec()))
+                .collect(),
+        };
+        (keypairs, members)
+    }
+
+    #[test]
+    fn loads_a_committee_from_keygen() {
+        let (keypairs, members) = members(4);
+        assert!(BlsProofs::load(1, &keypairs[1], &members).is_ok());
+    }
+
+    #[test]
+    fn refuses_a_missing_key() {
+        let (keypairs, mut members) = members(4);
+        members.bls_public_keys.remove(&2);
+        let error = BlsProofs::load(0, &keypairs[0], &members).err().unwrap();
+        assert!(error.to_string().contains("node 2 has no bls_public_key"));
+    }
+
+    #[test]
+    fn refuses_a_key_without_possession() {
+        // A rogue key is chosen from the others' public keys, so its owner
+        // can't sign with it; here the proof of another key stands in.
+        let (keypairs, mut members) = members(4);
+        let other = members.bls_proofs_of_possession[&0].clone();
+        members.bls_proofs_of_possession.insert(3, other);
+        let error = BlsProofs::load(0, &keypairs[0], &members).err().unwrap();
+        assert!(error.to_string().contains("bls_proof_of_possession of node 3 does not match"));
+    }
+
+    #[test]
+    fn refuses_another_nodes_key_as_ours() {
+        let (keypairs, members) = members(4);
+        let error = BlsProofs::load(0, &keypairs[1], &members).err().unwrap();
+        assert!(error.to_string().contains("does not match its private key"));
+    }
+}
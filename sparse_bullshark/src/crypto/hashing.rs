@@ -0,0 +1,200 @@
+use shared::hasher::{DefaultHasher, Hasher};
+use crate::types::{digest::Digest, vertex::Vertex};
+
+/// Version of the encodings below. Bump it whenever one of them changes, so
+/// nodes running different encodings can't agree on a hash by accident.
+pub const HASH_VERSION: u8 = 1;
+
+/// Domain tag for vertex hashes.
+pub const VERTEX_DOMAIN: &[u8] = b"sparse-bullshark/vertex";
+
+/// The protocol a vertex belongs to. Part of its hash, so a dense vertex
+/// can never pass for a sparse one.
+#[repr(u8)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Variant {
+    Dense = 0,
+    Sparse = 1,
+}
+
+/// Hashes typed fields under a domain tag and `HASH_VERSION`. Integers are
+/// big-endian and every variable-length field is prefixed with its length,
+/// so two different sequences of fields never encode to the same bytes.
+pub struct CanonicalHasher<H: Hasher = DefaultHasher> {
+    inner: H,
+}
+
+impl CanonicalHasher {
+    pub fn new(domain: &[u8]) -> Self {
+        Self::with_hasher(domain)
+    }
+}
+
+impl<H: Hasher> CanonicalHasher<H> {
+    /// Like `new`, with a hash function other than the build's.
+    pub fn with_hasher(domain: &[u8]) -> Self {
+        CanonicalHasher { inner: H::default() }.bytes(domain).u8(HASH_VERSION)
+    }
+
+    pub fn u8(mut self, value: u8) -> Self {
+        self.inner.update(&[value]);
+        self
+    }
+
+    pub fn u32(mut self, value: u32) -> Self {
+        self.inner.update(&value.to_be_bytes());
+        self
+    }
+
+    pub fn u64(mut self, value: u64) -> Self {
+        self.inner.update(&value.to_be_bytes());
+        self
+    }
+
+    /// A byte string, prefixed with its length.
+    pub fn bytes(mut self, value: &[u8]) -> Self {
+        self.inner.update(&(value.len() as u64).to_be_bytes());
+        self.inner.update(value);
+        self
+    }
+
+    /// A list of digests, prefixed with their count.
+    pub fn digests(mut self, values: &[Digest]) -> Self {
+        self.inner.update(&(values.len() as u64).to_be_bytes());
+        for value in values {
+            self.inner.update(value.as_bytes());
+        }
+        self
+    }
+
+    pub fn finish(self) -> Digest {
+        Digest(self.inner.finalize())
+    }
+}
+
+/// The hash of a vertex: every field but the hash itself and the signature
+/// over it, in declaration order.
+pub fn vertex_hash(variant: Variant, vertex: &Vertex) -> Digest {
+    encode_vertex(CanonicalHasher::new(VERTEX_DOMAIN), variant, vertex)
+}
+
+fn encode_vertex<H: Hasher>(hasher: CanonicalHasher<H>, variant: Variant, vertex: &Vertex) -> Digest {
+    hasher
+        .u8(variant as u8)
+        .u64(vertex.round)
+        .u32(vertex.source)
+        .bytes(&vertex.block)
+        .digests(&vertex.edges)
+        .bytes(&vertex.signed_round)
+        .bytes(&vertex.sample_proof)
+        .finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::vertex::NodeId;
+
+    struct TestVector {
+        variant: Variant,
+        round: u64,
+        source: NodeId,
+        block: &'static [u8],
+        edges: &'static [[u8; 32]],
+        signed_round: &'static [u8],
+        sample_proof: &'static [u8],
+        sha256: &'static str,
+        /// Only checked by builds with the `blake3` feature.
+        #[cfg_attr(not(feature = "blake3"), allow(dead_code))]
+        blake3: &'static str,
+    }
+
+    /// Known answers for `vertex_hash` under each hash function. The last two
+    /// only differ in where `block` ends and `signed_round` starts.
+    const TEST_VECTORS: &[TestVector] = &[
+        TestVector {
+            variant: Variant::Dense,
+            round: 1,
+            source: 0,
+            block: b"",
+            edges: &[[0; 32]],
+            signed_round: b"",
+            sample_proof: b"",
+            sha256: "094e48c81246aeb240a7d1ec7f7799df6f01a614d82fc0dfd27cd9da1eacd36a",
+            blake3: "1e3ce5273813b5e328c051cc67fb47f5a58be5826e7e9b7d4a8ab15db7dac9ea",
+        },
+        TestVector {
+            variant: Variant::Sparse,
+            round: 1,
+            source: 0,
+            block: b"",
+            edges: &[[0; 32]],
+            signed_round: b"",
+            sample_proof: b"",
+            sha256: "11a11eb81bcec55aeae2ad9d54298f60dbe8948eb83f1b359fa04495636f4362",
+            blake3: "142fc1a0127305e83a3eeb23a3d844d485d92df914c808b4f607c16fd654b183",
+        },
+        TestVector {
+            variant: Variant::Sparse,
+            round: 7,
+            source: 3,
+            block: b"block",
+            edges: &[[1; 32], [2; 32]],
+            signed_round: &[0xaa; 64],
+            sample_proof: &[0xbb; 16],
+            sha256: "d3372437cf5397c2f60973bd8262ca603cd9906dd21a59d4a163bfb07571ff67",
+            blake3: "c7fa65c0f6cfec79959960bb86fd49608e52aff61baabb67602a9e6650fb0d88",
+        },
+        TestVector {
+            variant: Variant::Dense,
+            round: 2,
+            source: 1,
+            block: b"ab",
+            edges: &[],
+            signed_round: b"c",
+            sample_proof: b"",
+            sha256: "d557c28dc4cf3f7866ae910e7f1000f1e1090e09a0e643dbdfb7823b6caff618",
+            blake3: "457757032cddcb3ea64cd0fd86484702efd2d96554f4537a3da4fca4b401fbc0",
+        },
+        TestVector {
+            variant: Variant::Dense,
+            round: 2,
+            source: 1,
+            block: b"a",
+            edges: &[],
+            signed_round: b"bc",
+            sample_proof: b"",
+            sha256: "0b9a7019732b6b9789bd8c56c96bd41cf5fbd499b7f5839981096699d17c87d9",
+            blake3: "5c756b30dd2105012d9dce0f5abe2e8ca1fe7ec1b98cc2096b654b7f94218293",
+        },
+    ];
+
+    /// Checks `vertex_hash`, computed with `H`, against the answers `expected` picks.
+    fn check_vectors<H: Hasher>(expected: impl Fn(&TestVector) -> &'static str) {
+        for (i, vector) in TEST_VECTORS.iter().enumerate() {
+            let vertex = Vertex {
+                hash: Digest::default(),
+                round: vector.round,
+                source: vector.source,
+                block: vector.block.to_vec(),
+                edges: vector.edges.iter().map(|edge| Digest(*edge)).collect(),
+                signed_round: vector.signed_round.to_vec(),
+                sample_proof: vector.sample_proof.to_vec(),
+                signature: vec![],
+            };
+            let hash = encode_vertex(CanonicalHasher::<H>::with_hasher(VERTEX_DOMAIN), vector.variant, &vertex);
+            assert_eq!(hash.to_string(), expected(vector), "{} test vector {}", H::NAME, i);
+        }
+    }
+
+    #[test]
+    fn sha256_vectors() {
+        check_vectors::<shared::hasher::Sha256Hasher>(|vector| vector.sha256);
+    }
+
+    #[cfg(feature = "blake3")]
+    #[test]
+    fn blake3_vectors() {
+        check_vectors::<shared::hasher::Blake3Hasher>(|vector| vector.blake3);
+    }
+}
@@ -1,2 +1,4 @@
 pub mod multisig;
-pub mod hashing;
\ No newline at end of file
+pub mod hashing;
+#[cfg(feature = "bls")]
+pub mod bls;
\ No newline at end of file
@@ -1,10 +1,17 @@
-use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier, ed25519::signature::Signature as _};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
 use serde::{Serialize, Deserialize};
 use std::collections::HashMap;
-use log::{error, warn};
+use std::error::Error;
+use std::fmt;
+use log::error;
+use shared::domain::committee::Committee as Members;
 use crate::types::vertex::NodeId;
 
-/// A structure to bundle signatures and their signers for the sample_proof.
+/// Domain tag for round signatures, so they cannot be confused with any other
+/// message signed by the same key.
+const ROUND_SIGNATURE_TAG: &[u8] = b"sparse-bullshark/round";
+
+/// A structure to bundle Ed25519 signatures and their signers for the sample_proof.
 /// This gets serialized into the `Vec<u8>` of the vertex's `sample_proof` field.
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct SampleProof {
@@ -12,63 +19,264 @@ pub struct SampleProof {
     pub signers: Vec<NodeId>,
 }
 
-/// Creates a cryptographic signature of the round number using the node's private key.
-pub fn sign_round(round: u64, private_key: &Keypair) -> Signature {
-    let message = round.to_be_bytes();
-    private_key.sign(&message)
+/// The set of nodes allowed to sign, and how many of them a proof needs.
+pub struct Committee<'a> {
+    pub public_keys: &'a HashMap<NodeId, PublicKey>,
+    pub quorum: usize,
+}
+
+/// Why a sample proof was rejected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProofError {
+    Malformed,
+    LengthMismatch { signatures: usize, signers: usize },
+    NotEnoughSigners { signers: usize, quorum: usize },
+    DuplicateSigner(NodeId),
+    /// Signers must be listed in strictly increasing order, so a proof has a
+    /// single encoding for a given set of signers.
+    UnorderedSigners,
+    UnknownSigner(NodeId),
+    InvalidSignature,
+}
+
+impl fmt::Display for ProofError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProofError::Malformed => write!(f, "malformed sample proof"),
+            ProofError::LengthMismatch { signatures, signers } => {
+                write!(f, "sample proof has {} signatures for {} signers", signatures, signers)
+            }
+            ProofError::NotEnoughSigners { signers, quorum } => {
+                write!(f, "sample proof has {} signers, {} required", signers, quorum)
+            }
+            ProofError::DuplicateSigner(id) => write!(f, "duplicate signer {} in sample proof", id),
+            ProofError::UnorderedSigners => write!(f, "sample proof signers are not in ascending order"),
+            ProofError::UnknownSigner(id) => write!(f, "unknown signer {} in sample proof", id),
+            ProofError::InvalidSignature => write!(f, "invalid signature in sample proof"),
+        }
+    }
+}
+
+impl std::error::Error for ProofError {}
+
+/// The message signed for a round: a domain tag, the epoch and the round number.
+pub fn round_message(epoch: u64, round: u64) -> Vec<u8> {
+    let mut message = Vec::with_capacity(ROUND_SIGNATURE_TAG.len() + 16);
+    message.extend_from_slice(ROUND_SIGNATURE_TAG);
+    message.extend_from_slice(&epoch.to_be_bytes());
+    message.extend_from_slice(&round.to_be_bytes());
+    message
+}
+
+/// A signature scheme for round signatures and the sample proofs built from them.
+pub trait ProofScheme {
+    /// Signs `round` of `epoch`. The bytes go into the vertex's `signed_round`.
+    fn sign_round(&self, epoch: u64, round: u64, private_key: &Keypair) -> Vec<u8>;
+
+    /// Checks a single round signature, as carried by a received vertex.
+    fn verify_round(&self, epoch: u64, round: u64, signer: NodeId, signature: &[u8], committee: &Committee) -> Result<(), ProofError>;
+
+    /// Combines already verified round signatures, in canonical signer order,
+    /// into a serialized sample proof.
+    fn aggregate(&self, partials: &[(NodeId, &[u8])]) -> Vec<u8>;
+
+    /// Validates a serialized sample proof for `round` of `epoch` and returns its signers.
+    fn validate(&self, epoch: u64, round: u64, sample_proof: &[u8], committee: &Committee) -> Result<Vec<NodeId>, ProofError>;
 }
 
-/// Aggregates a collection of signatures and signer IDs into a serializable proof.
-pub fn aggregate(signatures: Vec<Signature>, signers: Vec<NodeId>) -> Vec<u8> {
-    let proof = SampleProof {
-        signatures,
-        signers,
-    };
-
-    // Serialize the proof structure into a byte vector for transport.
-    bincode::serialize(&proof).unwrap_or_else(|e| {
-        error!("Failed to serialize sample proof: {}", e);
-        vec![]
-    })
+/// The sample proof scheme compiled in: Ed25519 batches by default, BLS
+/// aggregate signatures with the `bls` feature.
+#[cfg(not(feature = "bls"))]
+pub type RoundProofs = Ed25519Proofs;
+#[cfg(feature = "bls")]
+pub type RoundProofs = crate::crypto::bls::BlsProofs;
+
+/// Sample proofs as a list of full Ed25519 signatures plus their signers,
+/// checked with batch verification. Proof size grows with the number of signers.
+#[cfg_attr(feature = "bls", allow(dead_code))]
+pub struct Ed25519Proofs;
+
+#[cfg_attr(feature = "bls", allow(dead_code))]
+impl Ed25519Proofs {
+    pub fn load(_my_id: NodeId, _private_key: &Keypair, _members: &Members) -> Result<Self, Box<dyn Error>> {
+        Ok(Ed25519Proofs)
+    }
 }
 
-/// Validates a serialized sample proof.
-/// It deserializes the proof and then verifies each signature against the
-/// corresponding public key of the signer.
-pub fn validate(
-    round: u64,
-    sample_proof: &[u8],
-    public_keys: &HashMap<NodeId, PublicKey>,
-) -> bool {
-    let proof: SampleProof = match bincode::deserialize(sample_proof) {
-        Ok(p) => p,
-        Err(e) => {
-            warn!("Failed to deserialize sample proof: {}", e);
-            return false;
+impl ProofScheme for Ed25519Proofs {
+    /// Creates a cryptographic signature of the epoch and round number using the node's private key.
+    fn sign_round(&self, epoch: u64, round: u64, private_key: &Keypair) -> Vec<u8> {
+        private_key.sign(&round_message(epoch, round)).to_bytes().to_vec()
+    }
+
+    fn verify_round(&self, epoch: u64, round: u64, signer: NodeId, signature: &[u8], committee: &Committee) -> Result<(), ProofError> {
+        let public_key = committee.public_keys.get(&signer).ok_or(ProofError::UnknownSigner(signer))?;
+        let signature = Signature::from_bytes(signature).map_err(|_| ProofError::Malformed)?;
+        public_key.verify(&round_message(epoch, round), &signature).map_err(|_| ProofError::InvalidSignature)
+    }
+
+    /// Aggregates a collection of signatures and signer IDs into a serializable proof.
+    fn aggregate(&self, partials: &[(NodeId, &[u8])]) -> Vec<u8> {
+        let mut signatures = Vec::with_capacity(partials.len());
+        let mut signers = Vec::with_capacity(partials.len());
+        for (signer, bytes) in partials {
+            if let Ok(signature) = Signature::from_bytes(bytes) {
+                signatures.push(signature);
+                signers.push(*signer);
+            }
         }
-    };
+        let proof = SampleProof {
+            signatures,
+            signers,
+        };
 
-    if proof.signatures.len() != proof.signers.len() {
-        warn!("Mismatched number of signatures and signers in proof.");
-        return false;
+        // Serialize the proof structure into a byte vector for transport.
+        bincode::serialize(&proof).unwrap_or_else(|e| {
+            error!("Failed to serialize sample proof: {}", e);
+            vec![]
+        })
     }
 
-    let message = round.to_be_bytes();
-    let mut messages: Vec<&[u8]> = Vec::new();
-    let mut signatures_to_verify: Vec<Signature> = Vec::new();
-    let mut keys_to_verify: Vec<PublicKey> = Vec::new();
+    /// It deserializes the proof, checks that it carries a quorum of distinct
+    /// committee members in ascending order and then verifies each signature against the
+    /// corresponding public key of the signer.
+    fn validate(&self, epoch: u64, round: u64, sample_proof: &[u8], committee: &Committee) -> Result<Vec<NodeId>, ProofError> {
+        let proof: SampleProof = bincode::deserialize(sample_proof).map_err(|_| ProofError::Malformed)?;
+
+        if proof.signatures.len() != proof.signers.len() {
+            return Err(ProofError::LengthMismatch {
+                signatures: proof.signatures.len(),
+                signers: proof.signers.len(),
+            });
+        }
+        if proof.signers.len() < committee.quorum {
+            return Err(ProofError::NotEnoughSigners {
+                signers: proof.signers.len(),
+                quorum: committee.quorum,
+            });
+        }
 
-    for (sig, signer_id) in proof.signatures.iter().zip(proof.signers.iter()) {
-        if let Some(public_key) = public_keys.get(signer_id) {
+        let message = round_message(epoch, round);
+        let mut previous = None;
+        let mut messages: Vec<&[u8]> = Vec::new();
+        let mut keys_to_verify: Vec<PublicKey> = Vec::new();
+
+        for signer_id in &proof.signers {
+            match previous {
+                Some(previous) if previous == *signer_id => return Err(ProofError::DuplicateSigner(*signer_id)),
+                Some(previous) if previous > *signer_id => return Err(ProofError::UnorderedSigners),
+                _ => previous = Some(*signer_id),
+            }
+            // A signer must have a known public key
+            let public_key = committee.public_keys.get(signer_id).ok_or(ProofError::UnknownSigner(*signer_id))?;
             messages.push(&message);
-            signatures_to_verify.push(*sig);
             keys_to_verify.push(*public_key);
-        } else {
-            warn!("Public key not found for signer ID: {}", signer_id);
-            return false; // A signer must have a known public key
         }
+
+        // Use batch verification for efficiency, as recommended by the library.
+        ed25519_dalek::verify_batch(&messages, &proof.signatures, &keys_to_verify)
+            .map_err(|_| ProofError::InvalidSignature)?;
+        Ok(proof.signers)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use shared::keys::generate_keypair;
+
+    const EPOCH: u64 = 7;
+    const ROUND: u64 = 3;
+
+    fn committee_keys(n: u32) -> (Vec<Keypair>, HashMap<NodeId, PublicKey>) {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        let keypairs: Vec<Keypair> = (0..n).map(|_| generate_keypair(&mut rng)).collect();
+        let public_keys = keypairs.iter().enumerate().map(|(id, keypair)| (id as NodeId, keypair.public)).collect();
+        (keypairs, public_keys)
+    }
+
+    /// A proof over `signers`, each signing `round` of `epoch`, bypassing `aggregate`.
+    fn proof(keypairs: &[Keypair], signers: &[NodeId], epoch: u64, round: u64) -> SampleProof {
+        SampleProof {
+            signatures: signers.iter().map(|id| keypairs[*id as usize].sign(&round_message(epoch, round))).collect(),
+            signers: signers.to_vec(),
+        }
+    }
+
+    fn validate(public_keys: &HashMap<NodeId, PublicKey>, proof: &SampleProof) -> Result<Vec<NodeId>, ProofError> {
+        let committee = Committee { public_keys, quorum: 3 };
+        Ed25519Proofs.validate(EPOCH, ROUND, &bincode::serialize(proof).unwrap(), &committee)
+    }
+
+    #[test]
+    fn accepts_an_aggregated_quorum() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let signatures: Vec<(NodeId, Vec<u8>)> = [0, 2, 3].iter()
+            .map(|id| (*id, Ed25519Proofs.sign_round(EPOCH, ROUND, &keypairs[*id as usize])))
+            .collect();
+        let partials: Vec<(NodeId, &[u8])> = signatures.iter().map(|(id, bytes)| (*id, bytes.as_slice())).collect();
+        let committee = Committee { public_keys: &public_keys, quorum: 3 };
+        let proof = Ed25519Proofs.aggregate(&partials);
+        assert_eq!(Ed25519Proofs.validate(EPOCH, ROUND, &proof, &committee), Ok(vec![0, 2, 3]));
+    }
+
+    #[test]
+    fn rejects_garbage() {
+        let (_, public_keys) = committee_keys(4);
+        let committee = Committee { public_keys: &public_keys, quorum: 3 };
+        assert_eq!(Ed25519Proofs.validate(EPOCH, ROUND, &[1, 2, 3], &committee), Err(ProofError::Malformed));
+    }
+
+    #[test]
+    fn rejects_too_few_signers() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 1], EPOCH, ROUND);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::NotEnoughSigners { signers: 2, quorum: 3 }));
+    }
+
+    #[test]
+    fn rejects_a_duplicate_signer() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 1, 1], EPOCH, ROUND);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::DuplicateSigner(1)));
+    }
+
+    #[test]
+    fn rejects_unordered_signers() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 2, 1], EPOCH, ROUND);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::UnorderedSigners));
+    }
+
+    #[test]
+    fn rejects_an_unknown_signer() {
+        let (keypairs, mut public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 1, 3], EPOCH, ROUND);
+        public_keys.remove(&3);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::UnknownSigner(3)));
     }
-    
-    // Use batch verification for efficiency, as recommended by the library.
-    ed25519_dalek::verify_batch(&messages, &signatures_to_verify, &keys_to_verify).is_ok()
-}
\ No newline at end of file
+
+    #[test]
+    fn rejects_mismatched_lengths() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let mut proof = proof(&keypairs, &[0, 1, 2], EPOCH, ROUND);
+        proof.signatures.pop();
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::LengthMismatch { signatures: 2, signers: 3 }));
+    }
+
+    #[test]
+    fn rejects_a_proof_from_another_round() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 1, 2], EPOCH, ROUND - 1);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::InvalidSignature));
+    }
+
+    #[test]
+    fn rejects_a_proof_from_another_epoch() {
+        let (keypairs, public_keys) = committee_keys(4);
+        let proof = proof(&keypairs, &[0, 1, 2], EPOCH + 1, ROUND);
+        assert_eq!(validate(&public_keys, &proof), Err(ProofError::InvalidSignature));
+    }
+}
@@ -0,0 +1,97 @@
+use serde::{Deserialize, Serialize};
+use shared::hasher::{DefaultHasher, Hasher};
+use shared::domain::transaction::Transaction;
+use crate::execution::{ExecutionError, StateMachine};
+use crate::types::digest::Digest;
+
+/// Counts the blocks and transactions executed. Blocks are decoded the way
+/// the transaction generator encodes them, so it also checks that every
+/// ordered block is well formed.
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq, Eq)]
+pub struct CounterMachine {
+    pub blocks: u64,
+    pub transactions: u64,
+}
+
+impl StateMachine for CounterMachine {
+    fn execute(&mut self, block: &[u8]) -> Result<(), ExecutionError> {
+        // The genesis vertex carries no block.
+        if block.is_empty() {
+            return Ok(());
+        }
+        let transactions: Vec<Transaction> = bincode::deserialize(block)
+            .map_err(|e| ExecutionError::InvalidBlock(e.to_string()))?;
+        self.blocks += 1;
+        self.transactions += transactions.len() as u64;
+        Ok(())
+    }
+
+    fn state_root(&self) -> Digest {
+        DefaultHasher::default()
+            .chain_update(self.blocks.to_be_bytes())
+            .chain_update(self.transactions.to_be_bytes())
+            .finalize()
+            .into()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Failed to serialize counter state")
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), ExecutionError> {
+        *self = bincode::deserialize(snapshot).map_err(|e| ExecutionError::InvalidSnapshot(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn block(transactions: usize) -> Vec<u8> {
+        let transactions: Vec<Transaction> = (0..transactions).map(|i| Transaction::Delete { key: i.to_string() }).collect();
+        bincode::serialize(&transactions).unwrap()
+    }
+
+    #[test]
+    fn counts_blocks_and_transactions() {
+        let mut counter = CounterMachine::default();
+        counter.execute(&block(3)).unwrap();
+        counter.execute(&block(0)).unwrap();
+        counter.execute(&block(2)).unwrap();
+        assert_eq!(counter, CounterMachine { blocks: 3, transactions: 5 });
+    }
+
+    #[test]
+    fn skips_the_empty_genesis_block() {
+        let mut counter = CounterMachine::default();
+        counter.execute(&[]).unwrap();
+        assert_eq!(counter, CounterMachine::default());
+    }
+
+    #[test]
+    fn rejects_a_malformed_block() {
+        let mut counter = CounterMachine::default();
+        counter.execute(&block(1)).unwrap();
+        assert!(counter.execute(&[0xff; 3]).is_err());
+        assert_eq!(counter, CounterMachine { blocks: 1, transactions: 1 });
+    }
+
+    #[test]
+    fn the_root_follows_both_counts() {
+        let root = |blocks, transactions| CounterMachine { blocks, transactions }.state_root();
+        assert_ne!(root(1, 2), root(2, 2));
+        assert_ne!(root(1, 2), root(1, 3));
+        assert_ne!(root(1, 2), root(2, 1));
+        assert_eq!(root(1, 2), root(1, 2));
+    }
+
+    #[test]
+    fn restores_a_snapshot() {
+        let counter = CounterMachine { blocks: 4, transactions: 9 };
+        let mut restored = CounterMachine::default();
+        restored.restore(&counter.snapshot()).unwrap();
+        assert_eq!(restored, counter);
+        assert_eq!(restored.state_root(), counter.state_root());
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::BTreeMap;
+use log::trace;
+use shared::hasher::{DefaultHasher, Hasher};
+use shared::domain::transaction::Transaction;
+use crate::execution::{ExecutionError, StateMachine};
+use crate::types::digest::Digest;
+
+/// Prefixes keeping leaf and inner node hashes apart, so a leaf can't be
+/// passed off as an inner node.
+const LEAF_PREFIX: u8 = 0;
+const NODE_PREFIX: u8 = 1;
+
+/// The reference application: a key-value store replicated by applying every
+/// ordered block of puts, gets and deletes. The state root is a Merkle root
+/// over the entries in key order.
+#[derive(Default)]
+pub struct KvStore {
+    pub entries: BTreeMap<String, String>,
+}
+
+impl KvStore {
+    pub fn get(&self, key: &str) -> Option<&String> {
+        self.entries.get(key)
+    }
+
+    fn leaf_hash(key: &str, value: &str) -> Digest {
+        DefaultHasher::default()
+            .chain_update([LEAF_PREFIX])
+            .chain_update((key.len() as u64).to_be_bytes())
+            .chain_update(key)
+            .chain_update((value.len() as u64).to_be_bytes())
+            .chain_update(value)
+            .finalize()
+            .into()
+    }
+}
+
+impl StateMachine for KvStore {
+    /// Blocks are applied whole or not at all: one that doesn't decode
+    /// changes nothing.
+    fn execute(&mut self, block: &[u8]) -> Result<(), ExecutionError> {
+        // The genesis vertex carries no block.
+        if block.is_empty() {
+            return Ok(());
+        }
+        let transactions: Vec<Transaction> = bincode::deserialize(block)
+            .map_err(|e| ExecutionError::InvalidBlock(e.to_string()))?;
+        for transaction in transactions {
+            match transaction {
+                Transaction::Put { key, value } => {
+                    self.entries.insert(key, value);
+                }
+                Transaction::Get { key } => {
+                    trace!("Get {}: {:?}", key, self.get(&key).map(|value| value.len()));
+                }
+                Transaction::Delete { key } => {
+                    self.entries.remove(&key);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Hashes the leaves pairwise up to the root; an odd node out moves up a
+    /// level unchanged. The empty store has the hash of nothing as its root.
+    fn state_root(&self) -> Digest {
+        let mut level: Vec<Digest> = self.entries.iter()
+            .map(|(key, value)| Self::leaf_hash(key, value))
+            .collect();
+        if level.is_empty() {
+            return DefaultHasher::digest(&[]).into();
+        }
+        while level.len() > 1 {
+            level = level.chunks(2).map(|pair| match pair {
+                [left, right] => DefaultHasher::default()
+                    .chain_update([NODE_PREFIX])
+                    .chain_update(left)
+                    .chain_update(right)
+                    .finalize()
+                    .into(),
+                [single] => *single,
+                _ => unreachable!(),
+            }).collect();
+        }
+        level.remove(0)
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        bincode::serialize(&self.entries).expect("Failed to serialize key-value store")
+    }
+
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), ExecutionError> {
+        self.entries = bincode::deserialize(snapshot).map_err(|e| ExecutionError::InvalidSnapshot(e.to_string()))?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(key: &str, value: &str) -> Transaction {
+        Transaction::Put { key: key.to_string(), value: value.to_string() }
+    }
+
+    fn delete(key: &str) -> Transaction {
+        Transaction::Delete { key: key.to_string() }
+    }
+
+    fn block(transactions: &[Transaction]) -> Vec<u8> {
+        bincode::serialize(transactions).unwrap()
+    }
+
+    fn store(transactions: &[Transaction]) -> KvStore {
+        let mut store = KvStore::default();
+        store.execute(&block(transactions)).unwrap();
+        store
+    }
+
+    fn node_hash(left: &Digest, right: &Digest) -> Digest {
+        DefaultHasher::default().chain_update([NODE_PREFIX]).chain_update(left).chain_update(right).finalize().into()
+    }
+
+    #[test]
+    fn applies_transactions_in_order() {
+        let mut store = store(&[put("a", "1"), put("b", "2"), delete("a"), put("b", "3")]);
+        assert_eq!(store.get("a"), None);
+        assert_eq!(store.get("b").map(String::as_str), Some("3"));
+
+        store.execute(&block(&[delete("b"), put("a", "4"), Transaction::Get { key: "a".to_string() }])).unwrap();
+        assert_eq!(store.get("a").map(String::as_str), Some("4"));
+        assert_eq!(store.get("b"), None);
+    }
+
+    #[test]
+    fn an_undecodable_block_changes_nothing() {
+        let mut store = store(&[put("a", "1")]);
+        let root = store.state_root();
+        assert!(matches!(store.execute(b"not a block"), Err(ExecutionError::InvalidBlock(_))));
+        assert_eq!(store.get("a").map(String::as_str), Some("1"));
+        assert_eq!(store.state_root(), root);
+    }
+
+    #[test]
+    fn the_empty_store_has_the_hash_of_nothing_as_root() {
+        assert_eq!(KvStore::default().state_root(), Digest::from(DefaultHasher::digest(&[])));
+        // Executing the genesis block doesn't change that.
+        assert_eq!(store(&[]).state_root(), KvStore::default().state_root());
+    }
+
+    #[test]
+    fn a_single_entry_is_its_own_root() {
+        assert_eq!(store(&[put("a", "1")]).state_root(), KvStore::leaf_hash("a", "1"));
+    }
+
+    #[test]
+    fn an_odd_leaf_moves_up_unchanged() {
+        let store = store(&[put("a", "1"), put("b", "2"), put("c", "3")]);
+        let leaves = [KvStore::leaf_hash("a", "1"), KvStore::leaf_hash("b", "2"), KvStore::leaf_hash("c", "3")];
+        assert_eq!(store.state_root(), node_hash(&node_hash(&leaves[0], &leaves[1]), &leaves[2]));
+    }
+
+    #[test]
+    fn the_root_depends_on_the_entries_only() {
+        let forward = store(&[put("a", "1"), put("b", "2"), put("c", "3")]);
+        let backward = store(&[put("c", "3"), put("b", "2"), put("a", "1")]);
+        let detour = store(&[put("b", "0"), put("c", "3"), put("a", "1"), put("d", "4"), put("b", "2"), delete("d")]);
+        assert_eq!(forward.state_root(), backward.state_root());
+        assert_eq!(forward.state_root(), detour.state_root());
+        assert_ne!(forward.state_root(), store(&[put("a", "1"), put("b", "2"), put("c", "4")]).state_root());
+    }
+
+    #[test]
+    fn restores_a_snapshot() {
+        let original = store(&[put("a", "1"), put("b", "2"), put("c", "3")]);
+        let mut restored = store(&[put("z", "0")]);
+        restored.restore(&original.snapshot()).unwrap();
+        assert_eq!(restored.entries, original.entries);
+        assert_eq!(restored.state_root(), original.state_root());
+
+        assert!(matches!(restored.restore(b"\xff"), Err(ExecutionError::InvalidSnapshot(_))));
+        assert_eq!(restored.entries, original.entries);
+    }
+}
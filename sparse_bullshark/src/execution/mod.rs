@@ -0,0 +1,122 @@
+pub mod counter;
+pub mod kv;
+
+use std::fmt;
+use crate::types::digest::Digest;
+
+/// Why a block or a snapshot was rejected by the state machine.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExecutionError {
+    InvalidBlock(String),
+    InvalidSnapshot(String),
+}
+
+impl fmt::Display for ExecutionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ExecutionError::InvalidBlock(reason) => write!(f, "invalid block: {}", reason),
+            ExecutionError::InvalidSnapshot(reason) => write!(f, "invalid snapshot: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for ExecutionError {}
+
+/// The application replicated by consensus. The node executes every ordered
+/// block, in commit order, on each replica, so implementations must be
+/// deterministic: the same blocks must lead to the same `state_root` everywhere.
+pub trait StateMachine: Send {
+    /// Applies one ordered block. A rejected block leaves the state as it was;
+    /// every correct replica rejects it as well, so ordering goes on.
+    fn execute(&mut self, block: &[u8]) -> Result<(), ExecutionError>;
+
+    /// A digest of the current state, equal on replicas that executed the
+    /// same blocks.
+    fn state_root(&self) -> Digest;
+
+    /// Serializes the whole state.
+    fn snapshot(&self) -> Vec<u8>;
+
+    /// Replaces the state with one taken by `snapshot`.
+    fn restore(&mut self, snapshot: &[u8]) -> Result<(), ExecutionError>;
+}
+
+/// Executes nothing; for running consensus on its own.
+pub struct NoopMachine;
+
+impl StateMachine for NoopMachine {
+    fn execute(&mut self, _block: &[u8]) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+
+    fn state_root(&self) -> Digest {
+        Digest::default()
+    }
+
+    fn snapshot(&self) -> Vec<u8> {
+        vec![]
+    }
+
+    fn restore(&mut self, _snapshot: &[u8]) -> Result<(), ExecutionError> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::{counter::CounterMachine, kv::KvStore};
+    use shared::domain::transaction::Transaction;
+
+    fn blocks() -> Vec<Vec<u8>> {
+        (0..5).map(|i| {
+            let transactions: Vec<Transaction> = (0..i).map(|j| Transaction::Put { key: j.to_string(), value: i.to_string() }).collect();
+            bincode::serialize(&transactions).unwrap()
+        }).collect()
+    }
+
+    /// What every state machine owes the node: replicas executing the same
+    /// blocks agree on the root, a rejected block changes nothing and a
+    /// snapshot restores the root it was taken at.
+    fn check_contract<M: StateMachine + Default>() {
+        let (mut replica, mut other) = (M::default(), M::default());
+        for block in blocks() {
+            replica.execute(&block).unwrap();
+            other.execute(&block).unwrap();
+            assert_eq!(replica.state_root(), other.state_root());
+        }
+
+        let root = replica.state_root();
+        assert!(matches!(replica.execute(b"not a block"), Err(ExecutionError::InvalidBlock(_))));
+        assert_eq!(replica.state_root(), root);
+
+        let mut restored = M::default();
+        restored.restore(&replica.snapshot()).unwrap();
+        assert_eq!(restored.state_root(), root);
+        assert_ne!(M::default().state_root(), root);
+        assert!(matches!(restored.restore(b"not a snapshot"), Err(ExecutionError::InvalidSnapshot(_))));
+    }
+
+    #[test]
+    fn the_counter_keeps_the_contract() {
+        check_contract::<CounterMachine>();
+    }
+
+    #[test]
+    fn the_kv_store_keeps_the_contract() {
+        check_contract::<KvStore>();
+    }
+
+    #[test]
+    fn the_noop_machine_has_no_state() {
+        let mut machine = NoopMachine;
+        for block in blocks() {
+            machine.execute(&block).unwrap();
+        }
+        machine.execute(b"not a block").unwrap();
+        assert_eq!(machine.state_root(), Digest::default());
+        assert!(machine.snapshot().is_empty());
+        machine.restore(&[]).unwrap();
+        assert_eq!(machine.state_root(), Digest::default());
+    }
+}
@@ -4,44 +4,132 @@ mod network;
 mod types;
 mod utils;
 mod config;
+mod storage;
+mod execution;
+mod cli;
 
-use std::env;
+use std::error::Error;
+use std::io;
+use clap::Parser;
 use env_logger::Env;
-use log::{info, error,debug};
-use consensus::bullshark::Bullshark;
-use shared::initializer::{get_environment, get_private_key, get_public_keys};
+use log::{error,debug,info,warn};
+use shared::hasher::{DefaultHasher, Hasher};
+use cli::{Cli, Command, ConfigArgs, RunArgs};
+use config::{Application, CommitteeSource, ConfigError, NodeConfig, Protocol};
+use consensus::{bullshark::Bullshark, node::DagNode, policy::{CommitRule, EdgePolicy}};
+use storage::wal::Wal;
+use execution::{counter::CounterMachine, kv::KvStore};
+use shared::initializer::get_environment;
+use shared::keys::load_private_key;
 
 use consensus::sparse_bullshark::SparseBullshark;
 
+/// Sets up the state machine and storage the configuration asks for,
+/// recovering the node from its write-ahead log if there is one.
+fn configure<E: EdgePolicy, C: CommitRule>(node: &mut DagNode<E, C>) -> io::Result<()> {
+    match node.config.state_machine {
+        Application::Noop => {}
+        Application::Counter => node.state_machine = Box::new(CounterMachine::default()),
+        Application::Kv => node.state_machine = Box::new(KvStore::default()),
+    }
+    if let Some(data_dir) = node.config.paths.data_dir.clone() {
+        let path = data_dir.join(format!("node-{}.wal", node.environment.my_node.id));
+        let wal = Wal::open(&path)
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to open write-ahead log {}: {}", path.display(), e)))?;
+        node.storage = Box::new(wal);
+        node.recover()
+            .map_err(|e| io::Error::new(e.kind(), format!("failed to recover from {}: {}", path.display(), e)))?;
+    }
+    Ok(())
+}
+
+/// Reads the configuration file, then applies the environment variables and
+/// the `--set` overrides on top.
+fn load_config(args: &ConfigArgs) -> Result<NodeConfig, ConfigError> {
+    let config = NodeConfig::load(args.config.as_deref(), &args.overrides())?;
+    config.validate()?;
+    Ok(config)
+}
+
+/// Stand-in for the application on top of consensus: it just logs every
+/// committed sub-DAG it is handed.
+fn spawn_application<E: EdgePolicy, C: CommitRule>(node: &mut DagNode<E, C>) {
+    let my_id = node.environment.my_node.id;
+    let mut committed = node.subscribe();
+    tokio::spawn(async move {
+        while let Some(sub_dag) = committed.recv().await {
+            let block_bytes: usize = sub_dag.blocks.iter().map(|block| block.len()).sum();
+            // The full root, so the logs of replicas can be compared round by round.
+            info!(
+                "[Node {}] Committed anchor {:.8} of Node {} in round {}: {} vertices, {} block bytes, state root {}",
+                my_id, sub_dag.anchor, sub_dag.leader, sub_dag.round, sub_dag.vertices.len(), block_bytes, sub_dag.state_root
+            );
+        }
+    });
+}
+
+async fn run(args: RunArgs) -> Result<(), Box<dyn Error>> {
+    let mut config = load_config(&args.config)?;
+    if let Some(protocol) = args.protocol {
+        config.protocol = protocol;
+    }
+    // Load the committee, environment and crypto setup
+    let source = config.paths.committee_source()?;
+    if let CommitteeSource::Legacy { nodes, public_keys } = &source {
+        warn!(
+            "Reading the committee from {} and {}, which are deprecated; gen-committee writes a single committee file",
+            nodes.display(), public_keys.display()
+        );
+    }
+    let committee = source.load()?;
+    if committee.nodes.iter().any(|node| node.stake != committee.nodes[0].stake) {
+        warn!("The committee has unequal stakes, but quorums count nodes: stakes are ignored");
+    }
+    let env = get_environment(args.node_id, args.tx_size, args.tx_count, args.skip_signatures, &committee)
+        .map_err(|err| format!("Error loading environment: {}", err))?;
+    debug!("Successfully read environment: {:?}", env);
+    config.validate_committee(env.nodes.len())?;
+    debug!("Configuration: {:?}", config);
+
+    // Load the private key
+    let private_key = load_private_key(env.my_node.id, config.paths.private_key.as_deref())?;
+
+    match config.protocol {
+        Protocol::Dense => {
+            // --- Run Standard (Dense) Bullshark ---
+            let mut node = Bullshark::new(env, config, committee, private_key);
+            configure(&mut node)?;
+            spawn_application(&mut node);
+            node.start().await?;
+        }
+        Protocol::Sparse => {
+            // --- Run Sparse Bullshark (Default) ---
+            let mut node = SparseBullshark::new(env, config, committee, private_key)?;
+            configure(&mut node)?;
+            spawn_application(&mut node);
+            node.start().await?;
+        }
+    }
+    Ok(())
+}
+
 #[tokio::main(flavor = "multi_thread")]
 async fn main() {
     // Initialize the logger
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
 
-    // Read CLI args (e.g. path to env file or node ID)
-    let args: Vec<String> = env::args().collect();
-    let protocol_mode = env::var("PROTOCOL").unwrap_or_else(|_| "sparse".to_string()).to_lowercase();
-    // Load environment and crypto setup
-    match get_environment(args) {
-        Ok(env) => {
-            debug!("Successfully read environment: {:?}", env);
-
-            // Load public/private keys
-            let public_keys = get_public_keys();
-            let private_key = get_private_key(env.my_node.id);
-
-            if protocol_mode == "dense" || protocol_mode == "standard" {
-                // --- Run Standard (Dense) Bullshark ---
-                let node = Bullshark::new(env, public_keys, private_key);
-                node.start().await;
-            } else {
-                // --- Run Sparse Bullshark (Default) ---
-                let node = SparseBullshark::new(env, public_keys, private_key);
-                node.start().await;
-            }
-        }
-        Err(err) => {
-            error!("Error loading environment: {}", err);
+    let cli = Cli::parse();
+    let result = match cli.command {
+        Command::Run(args) => {
+            info!("Hashing with {}", DefaultHasher::NAME);
+            run(args).await
         }
+        Command::Keygen(args) => cli::keygen(&args),
+        Command::GenCommittee(args) => cli::gen_committee(&args),
+        Command::Inspect(args) => cli::inspect(&args),
+    };
+    if let Err(err) = result {
+        error!("{}", err);
+        std::process::exit(1);
     }
 }
@@ -1,7 +1,5 @@
-use ed25519_dalek::ed25519::signature;
 use tokio::io::AsyncWriteExt;
 use tokio::net::TcpStream;
-use bincode::serialize;
 use log::error;
 use crate::network::message::SparseMessage;
 use rand::rngs::OsRng;
@@ -11,8 +9,10 @@ use ed25519_dalek::Signer;
 use std::sync::Arc;
 
 pub const NONCE_BYTES_LENGTH: usize = 32;
+#[allow(dead_code)]
 pub const MESSAGE_BYTES_LENGTH: usize = 4; // Ensure this is defined here or imported
 
+#[allow(dead_code)]
 pub async fn reliable_broadcast(
     connections: &mut [Option<TcpStream>],
     message: &SparseMessage,
@@ -1,4 +1,5 @@
 use serde::{Serialize, Deserialize};
+use crate::types::equivocation::EquivocationEvidence;
 use crate::types::vertex::Vertex;
 use crate::types::vertex::NodeId;
 use crate::types::vertex::VertexHash;
@@ -24,12 +25,36 @@ pub struct EchoMessage {
 pub struct ReadyMessage {
     pub vertex_hash : VertexHash,
 }
+/// Request for vertices we are missing, by hash.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncRequestMessage {
+    pub hashes: Vec<VertexHash>,
+}
+/// The requested vertices the responder holds in its DAG.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SyncResponseMessage {
+    pub vertices: Vec<Vertex>,
+}
 
 /// Unified network message type for Sparse Bullshark.
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum SparseMessage {
     Vertex(VertexMessage),
-    RBC_Echo(EchoMessage),
-    RBC_Ready(ReadyMessage),
+    RbcEcho(EchoMessage),
+    RbcReady(ReadyMessage),
     Commit(CommitMessage),
+    SyncRequest(SyncRequestMessage),
+    SyncResponse(SyncResponseMessage),
+    /// Proof that a node proposed two vertices for one round.
+    Equivocation(Box<EquivocationEvidence>),
 }
+
+/// Who an outgoing message is for.
+#[derive(Clone, Copy, Debug)]
+pub enum Recipient {
+    All,
+    Node(NodeId),
+}
+
+/// A message queued for the dispatcher.
+pub type Outgoing = (Recipient, SparseMessage);
@@ -0,0 +1,78 @@
+pub mod wal;
+
+use std::io;
+use serde::{Deserialize, Serialize};
+use crate::types::vertex::{Vertex, VertexHash};
+
+/// One entry of the node's persistent state. Records are written before the
+/// node acts on them, so after a crash the log holds everything it may have
+/// told its peers.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum Record {
+    /// A peer's vertex inserted into the DAG.
+    Vertex(Vertex),
+    /// One of our own vertices, written before it is broadcast.
+    Proposal(Vertex),
+    /// We sent ECHO for `hash`. `round` is our round at the time.
+    RbcEcho { hash: VertexHash, round: u64 },
+    /// We sent READY for `hash`.
+    RbcReady { hash: VertexHash, round: u64 },
+    /// RBC delivered `hash`.
+    RbcDelivered { hash: VertexHash, round: u64 },
+    /// Every anchor up to `round` was ordered. The blocks ordered since the
+    /// last `Snapshot` are executed again from it after a restart.
+    LastOrderedRound { round: u64 },
+    /// Every anchor up to `round` was ordered, leaving the state machine in
+    /// `state` (its snapshot). Taken every `SNAPSHOT_INTERVAL` rounds only.
+    Snapshot { round: u64, state: Vec<u8> },
+}
+
+impl Record {
+    /// The round the record belongs to, for pruning.
+    pub fn round(&self) -> u64 {
+        match self {
+            Record::Vertex(vertex) | Record::Proposal(vertex) => vertex.round,
+            Record::RbcEcho { round, .. } | Record::RbcReady { round, .. } | Record::RbcDelivered { round, .. } => *round,
+            Record::LastOrderedRound { round } | Record::Snapshot { round, .. } => *round,
+        }
+    }
+
+    /// Whether the record has to be on disk, not just handed to the OS,
+    /// before we act on it. Losing one of these in a power failure could make
+    /// us sign or vote for something conflicting after the restart; anything
+    /// else can be fetched from peers again.
+    pub fn must_sync(&self) -> bool {
+        matches!(self, Record::Proposal(_) | Record::RbcEcho { .. } | Record::RbcReady { .. })
+    }
+}
+
+/// Where a node keeps its state across restarts.
+pub trait Storage: Send {
+    /// Appends `record`. Once this returns, the record survives a crash of
+    /// the process, and of the machine if `record.must_sync()`.
+    fn append(&mut self, record: &Record) -> io::Result<()>;
+
+    /// Reads back every record, oldest first.
+    fn load(&mut self) -> io::Result<Vec<Record>>;
+
+    /// Drops the records below `round`, following the DAG's garbage
+    /// collection. The latest `Snapshot` is always kept.
+    fn prune_below(&mut self, round: u64) -> io::Result<()>;
+}
+
+/// Keeps nothing: state is lost when the process exits.
+pub struct NoStorage;
+
+impl Storage for NoStorage {
+    fn append(&mut self, _record: &Record) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Record>> {
+        Ok(vec![])
+    }
+
+    fn prune_below(&mut self, _round: u64) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,217 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use log::warn;
+use sha2::{Digest, Sha256};
+use crate::storage::{Record, Storage};
+
+/// Bytes of the big-endian length in front of every record.
+const LENGTH_BYTES: usize = 4;
+/// Bytes of the checksum (a truncated SHA-256 of the record) after the length.
+const CHECKSUM_BYTES: usize = 4;
+/// The log is only rewritten once garbage collection moved this many rounds
+/// past the last rewrite, so most prune calls are free.
+const COMPACTION_INTERVAL: u64 = 100;
+
+fn checksum(payload: &[u8]) -> [u8; CHECKSUM_BYTES] {
+    let digest = Sha256::digest(payload);
+    [digest[0], digest[1], digest[2], digest[3]]
+}
+
+fn encode(record: &Record) -> io::Result<Vec<u8>> {
+    let payload = bincode::serialize(record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut frame = Vec::with_capacity(LENGTH_BYTES + CHECKSUM_BYTES + payload.len());
+    frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    frame.extend_from_slice(&checksum(&payload));
+    frame.extend_from_slice(&payload);
+    Ok(frame)
+}
+
+/// Decodes the records in `bytes`. Returns them with the length of the valid
+/// prefix: a crash in the middle of an append leaves a torn last record.
+fn decode(bytes: &[u8]) -> (Vec<Record>, usize) {
+    let mut records = Vec::new();
+    let mut offset = 0;
+    while bytes.len() - offset >= LENGTH_BYTES + CHECKSUM_BYTES {
+        let length = u32::from_be_bytes(bytes[offset..offset + LENGTH_BYTES].try_into().unwrap()) as usize;
+        let start = offset + LENGTH_BYTES + CHECKSUM_BYTES;
+        let Some(payload) = bytes.get(start..start + length) else { break };
+        if checksum(payload)[..] != bytes[offset + LENGTH_BYTES..start] {
+            break;
+        }
+        match bincode::deserialize(payload) {
+            Ok(record) => records.push(record),
+            Err(_) => break,
+        }
+        offset = start + length;
+    }
+    (records, offset)
+}
+
+/// An append-only write-ahead log in a single file. Every record is framed
+/// with its length and a checksum.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    compacted_below: u64,
+}
+
+impl Wal {
+    /// Opens the log at `path`, creating it if needed. A torn record at the
+    /// end, left by a crash, is cut off.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(&path)?;
+        let mut bytes = Vec::new();
+        file.read_to_end(&mut bytes)?;
+        let (_, valid) = decode(&bytes);
+        if valid < bytes.len() {
+            warn!("Truncating {} bytes of torn records at the end of {}", bytes.len() - valid, path.display());
+            file.set_len(valid as u64)?;
+            file.sync_all()?;
+        }
+        Ok(Wal { path, file, compacted_below: 0 })
+    }
+
+    /// Reads the records of the log at `path` without changing it, together
+    /// with the number of bytes of torn records at the end.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<(Vec<Record>, usize)> {
+        let bytes = fs::read(path)?;
+        let (records, valid) = decode(&bytes);
+        Ok((records, bytes.len() - valid))
+    }
+
+    /// Rewrites the log with only `records`, atomically replacing the old file.
+    fn rewrite(&mut self, records: &[Record]) -> io::Result<()> {
+        let tmp_path = self.path.with_extension("compacting");
+        let mut tmp = File::create(&tmp_path)?;
+        for record in records {
+            tmp.write_all(&encode(record)?)?;
+        }
+        tmp.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+        if let Some(dir) = self.path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+        self.file = OpenOptions::new().read(true).append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Storage for Wal {
+    fn append(&mut self, record: &Record) -> io::Result<()> {
+        self.file.write_all(&encode(record)?)?;
+        if record.must_sync() {
+            self.file.sync_data()?;
+        }
+        Ok(())
+    }
+
+    fn load(&mut self) -> io::Result<Vec<Record>> {
+        let bytes = fs::read(&self.path)?;
+        Ok(decode(&bytes).0)
+    }
+
+    fn prune_below(&mut self, round: u64) -> io::Result<()> {
+        if round < self.compacted_below + COMPACTION_INTERVAL {
+            return Ok(());
+        }
+        let records = self.load()?;
+        let snapshot = records.iter()
+            .filter(|record| matches!(record, Record::Snapshot { .. }))
+            .max_by_key(|record| record.round())
+            .cloned();
+        let mut kept: Vec<Record> = records.into_iter()
+            .filter(|record| !matches!(record, Record::Snapshot { .. }) && record.round() >= round)
+            .collect();
+        kept.extend(snapshot);
+        self.rewrite(&kept)?;
+        self.compacted_below = round;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::digest::{Digest, DIGEST_LENGTH};
+
+    fn records() -> Vec<Record> {
+        (1..=3).map(|round| Record::RbcDelivered { hash: Digest([round as u8; DIGEST_LENGTH]), round }).collect()
+    }
+
+    fn rounds(records: &[Record]) -> Vec<u64> {
+        records.iter().map(Record::round).collect()
+    }
+
+    fn encoded(records: &[Record]) -> Vec<u8> {
+        records.iter().flat_map(|record| encode(record).unwrap()).collect()
+    }
+
+    /// A fresh log file in the temp directory holding `bytes`.
+    fn log_with(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("sparse-bullshark-wal-{}-{}", std::process::id(), name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn decodes_whole_records() {
+        let bytes = encoded(&records());
+        let (decoded, valid) = decode(&bytes);
+        assert_eq!(rounds(&decoded), [1, 2, 3]);
+        assert_eq!(valid, bytes.len());
+    }
+
+    #[test]
+    fn stops_at_a_torn_last_record() {
+        let bytes = encoded(&records());
+        let intact = encoded(&records()[..2]).len();
+        // Cut inside the payload, and inside the header, of the last record.
+        for cut in [bytes.len() - 1, intact + LENGTH_BYTES] {
+            let (decoded, valid) = decode(&bytes[..cut]);
+            assert_eq!(rounds(&decoded), [1, 2]);
+            assert_eq!(valid, intact);
+        }
+    }
+
+    #[test]
+    fn stops_at_a_corrupted_checksum() {
+        let mut bytes = encoded(&records());
+        let second = encoded(&records()[..1]).len();
+        bytes[second + LENGTH_BYTES] ^= 0xff;
+        let (decoded, valid) = decode(&bytes);
+        assert_eq!(rounds(&decoded), [1]);
+        assert_eq!(valid, second);
+    }
+
+    #[test]
+    fn open_cuts_off_a_torn_record() {
+        let bytes = encoded(&records());
+        let path = log_with("torn", &bytes[..bytes.len() - 1]);
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len() as usize, encoded(&records()[..2]).len());
+
+        // New records follow the intact ones instead of the torn bytes.
+        wal.append(&records()[2]).unwrap();
+        assert_eq!(rounds(&wal.load().unwrap()), [1, 2, 3]);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn open_cuts_off_everything_after_a_corrupted_record() {
+        let mut bytes = encoded(&records());
+        let second = encoded(&records()[..1]).len();
+        let length = bytes.len();
+        bytes[length - 1] ^= 0xff;
+        bytes[second + LENGTH_BYTES + CHECKSUM_BYTES] ^= 0xff;
+        let path = log_with("corrupted", &bytes);
+        let mut wal = Wal::open(&path).unwrap();
+        assert_eq!(rounds(&wal.load().unwrap()), [1]);
+        assert_eq!(Wal::read(&path).unwrap().1, 0);
+        fs::remove_file(&path).unwrap();
+    }
+}
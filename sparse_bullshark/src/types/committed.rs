@@ -0,0 +1,17 @@
+use std::sync::Arc;
+use crate::types::digest::Digest;
+use crate::types::vertex::{NodeId, Vertex, VertexHash};
+
+/// What one committed anchor adds to the total order: the anchor and the
+/// part of its causal history no earlier anchor ordered, in delivery order.
+#[derive(Clone, Debug)]
+pub struct CommittedSubDag {
+    pub anchor: VertexHash,
+    pub leader: NodeId,
+    pub round: u64,
+    pub vertices: Vec<Arc<Vertex>>,
+    /// The blocks of `vertices`, in the same order.
+    pub blocks: Vec<Vec<u8>>,
+    /// The state machine's root once `blocks` were executed.
+    pub state_root: Digest,
+}
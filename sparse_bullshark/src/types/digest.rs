@@ -0,0 +1,62 @@
+use std::fmt;
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+pub const DIGEST_LENGTH: usize = 32;
+
+/// A 32-byte hash. Shown as lowercase hex; `{:.8}` shortens it for logs.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Digest(pub [u8; DIGEST_LENGTH]);
+
+impl Digest {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl From<[u8; DIGEST_LENGTH]> for Digest {
+    fn from(bytes: [u8; DIGEST_LENGTH]) -> Self {
+        Digest(bytes)
+    }
+}
+
+impl AsRef<[u8]> for Digest {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.pad(&hex::encode(self.0))
+    }
+}
+
+impl fmt::Debug for Digest {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Digest({})", self)
+    }
+}
+
+/// Hex in human readable formats (TOML, JSON), raw bytes in bincode.
+impl Serialize for Digest {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_str(&hex::encode(self.0))
+        } else {
+            self.0.serialize(serializer)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Digest {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            let text = String::deserialize(deserializer)?;
+            let mut bytes = [0; DIGEST_LENGTH];
+            hex::decode_to_slice(&text, &mut bytes).map_err(de::Error::custom)?;
+            Ok(Digest(bytes))
+        } else {
+            <[u8; DIGEST_LENGTH]>::deserialize(deserializer).map(Digest)
+        }
+    }
+}
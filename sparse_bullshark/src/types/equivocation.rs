@@ -0,0 +1,146 @@
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use serde::{Deserialize, Serialize};
+use crate::crypto::hashing::{CanonicalHasher, Variant};
+use crate::types::{digest::Digest, vertex::{NodeId, Vertex}};
+
+/// Domain tag for the digest a reporter signs.
+const EQUIVOCATION_DOMAIN: &[u8] = b"sparse-bullshark/equivocation";
+
+/// Two different vertices from the same source for the same round, both
+/// signed by it. Honest nodes propose once per round, so this is proof the
+/// source is faulty.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Equivocation {
+    pub source: NodeId,
+    pub round: u64,
+    /// The vertex inserted into the DAG.
+    pub first: Vertex,
+    /// The conflicting vertex that was rejected.
+    pub second: Vertex,
+}
+
+impl Equivocation {
+    /// Whether the two vertices really conflict: both `variant` vertices
+    /// signed by `source` (whose key is `source_key`) for `round`, and different.
+    pub fn is_valid(&self, source_key: &PublicKey, variant: Variant) -> bool {
+        let conflicting = [&self.first, &self.second].iter()
+            .all(|v| v.source == self.source && v.round == self.round && v.verify(source_key, variant));
+        conflicting && self.first.hash != self.second.hash
+    }
+
+    /// What the reporter signs.
+    fn digest(&self) -> Digest {
+        CanonicalHasher::new(EQUIVOCATION_DOMAIN)
+            .u32(self.source)
+            .u64(self.round)
+            .digests(&[self.first.hash, self.second.hash])
+            .finish()
+    }
+}
+
+/// An equivocation signed by the node that detected it, so it can be passed
+/// on to peers and attributed to its reporter.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EquivocationEvidence {
+    pub equivocation: Equivocation,
+    pub reporter: NodeId,
+    pub signature: Vec<u8>,
+}
+
+impl EquivocationEvidence {
+    pub fn sign(equivocation: Equivocation, reporter: NodeId, private_key: &Keypair) -> Self {
+        let signature = private_key.sign(equivocation.digest().as_bytes()).to_bytes().to_vec();
+        EquivocationEvidence { equivocation, reporter, signature }
+    }
+
+    /// Checks the reporter's signature and that the vertices conflict.
+    pub fn verify(&self, reporter_key: &PublicKey, source_key: &PublicKey, variant: Variant) -> bool {
+        let Ok(signature) = Signature::from_bytes(&self.signature) else { return false };
+        self.equivocation.is_valid(source_key, variant) && reporter_key.verify(self.equivocation.digest().as_bytes(), &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+    use rand_chacha::ChaCha20Rng;
+    use shared::keys::generate_keypair;
+
+    const VARIANT: Variant = Variant::Sparse;
+
+    /// The keys of a source and of a reporter.
+    fn keypairs() -> (Keypair, Keypair) {
+        let mut rng = ChaCha20Rng::seed_from_u64(0);
+        (generate_keypair(&mut rng), generate_keypair(&mut rng))
+    }
+
+    fn vertex(source_key: &Keypair, block: &[u8]) -> Vertex {
+        let mut vertex = Vertex {
+            hash: Digest::default(),
+            round: 3,
+            source: 1,
+            block: block.to_vec(),
+            edges: vec![],
+            signed_round: vec![],
+            sample_proof: vec![],
+            signature: vec![],
+        };
+        vertex.hash = vertex.calculate_hash(VARIANT);
+        vertex.sign(source_key);
+        vertex
+    }
+
+    fn evidence(source_key: &Keypair, reporter_key: &Keypair) -> EquivocationEvidence {
+        let equivocation = Equivocation { source: 1, round: 3, first: vertex(source_key, b"a"), second: vertex(source_key, b"b") };
+        EquivocationEvidence::sign(equivocation, 2, reporter_key)
+    }
+
+    #[test]
+    fn signed_evidence_verifies() {
+        let (source, reporter) = keypairs();
+        assert!(evidence(&source, &reporter).verify(&reporter.public, &source.public, VARIANT));
+    }
+
+    #[test]
+    fn rejects_evidence_signed_by_someone_else() {
+        let (source, reporter) = keypairs();
+        let evidence = evidence(&source, &reporter);
+        assert!(!evidence.verify(&source.public, &source.public, VARIANT));
+
+        let mut forged = evidence.clone();
+        forged.signature[0] ^= 1;
+        assert!(!forged.verify(&reporter.public, &source.public, VARIANT));
+    }
+
+    #[test]
+    fn rejects_evidence_with_a_swapped_vertex() {
+        let (source, reporter) = keypairs();
+        let mut evidence = evidence(&source, &reporter);
+        evidence.equivocation.second = vertex(&source, b"c");
+        assert!(!evidence.verify(&reporter.public, &source.public, VARIANT));
+    }
+
+    #[test]
+    fn rejects_vertices_that_dont_conflict() {
+        let (source, reporter) = keypairs();
+        // The same vertex twice.
+        let first = vertex(&source, b"a");
+        let equivocation = Equivocation { source: 1, round: 3, first: first.clone(), second: first };
+        assert!(!EquivocationEvidence::sign(equivocation, 2, &reporter).verify(&reporter.public, &source.public, VARIANT));
+
+        // A vertex the source never signed.
+        let mut unsigned = vertex(&source, b"b");
+        unsigned.signature = reporter.sign(unsigned.hash.as_bytes()).to_bytes().to_vec();
+        let equivocation = Equivocation { source: 1, round: 3, first: vertex(&source, b"a"), second: unsigned };
+        assert!(!EquivocationEvidence::sign(equivocation, 2, &reporter).verify(&reporter.public, &source.public, VARIANT));
+
+        // Vertices of different rounds.
+        let mut later = vertex(&source, b"b");
+        later.round = 4;
+        later.hash = later.calculate_hash(VARIANT);
+        later.sign(&source);
+        let equivocation = Equivocation { source: 1, round: 3, first: vertex(&source, b"a"), second: later };
+        assert!(!EquivocationEvidence::sign(equivocation, 2, &reporter).verify(&reporter.public, &source.public, VARIANT));
+    }
+}
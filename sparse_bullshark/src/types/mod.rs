@@ -1 +1,4 @@
-pub mod vertex;
\ No newline at end of file
+pub mod digest;
+pub mod vertex;
+pub mod committed;
+pub mod equivocation;
\ No newline at end of file
@@ -1,7 +1,10 @@
-use serde::{Serialize, Deserialize};
-use sha2::{Sha256, Digest};
+use ed25519_dalek::{Keypair, PublicKey, Signature, Signer, Verifier};
+use crate::crypto::hashing::{vertex_hash, Variant};
+use crate::types::digest::{Digest, DIGEST_LENGTH};
 pub type NodeId = u32;
-pub type VertexHash = Vec<u8>;
+pub type VertexHash = Digest;
+/// The hash of the genesis vertex every round 1 vertex links to.
+pub const GENESIS_HASH: VertexHash = Digest([0; DIGEST_LENGTH]);
 #[derive(Clone, Debug,serde::Deserialize, serde::Serialize, PartialEq, Eq, Hash)]
 pub struct Vertex{
     pub hash: VertexHash,
@@ -11,18 +14,24 @@ pub struct Vertex{
     pub edges : Vec<VertexHash>,
     pub signed_round : Vec<u8>,
     pub sample_proof: Vec<u8>,
+    /// The source's signature over `hash`, binding it to the whole vertex.
+    /// Not part of the hash itself.
+    pub signature: Vec<u8>,
 }
 impl Vertex {
-    pub fn calculate_hash(&self) -> VertexHash{
-        let mut hasher = Sha256::new();
-        hasher.update(&self.round.to_be_bytes());
-        hasher.update(&self.source.to_be_bytes());
-        hasher.update(&self.block);
-        for edge in &self.edges {
-            hasher.update(edge);
-        }
-        hasher.update(&self.signed_round);
-        hasher.update(&self.sample_proof);
-        hasher.finalize().to_vec()
+    /// The hash of the vertex as a vertex of `variant`, see `crypto::hashing`.
+    pub fn calculate_hash(&self, variant: Variant) -> VertexHash{
+        vertex_hash(variant, self)
     }
-}
\ No newline at end of file
+
+    /// Signs the vertex as its source. `hash` must be final.
+    pub fn sign(&mut self, private_key: &Keypair) {
+        self.signature = private_key.sign(self.hash.as_bytes()).to_bytes().to_vec();
+    }
+
+    /// Whether `hash` matches the contents and is signed by `public_key`.
+    pub fn verify(&self, public_key: &PublicKey, variant: Variant) -> bool {
+        let Ok(signature) = Signature::from_bytes(&self.signature) else { return false };
+        self.hash == self.calculate_hash(variant) && public_key.verify(self.hash.as_bytes(), &signature).is_ok()
+    }
+}
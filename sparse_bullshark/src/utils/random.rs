@@ -1,16 +1,71 @@
-use crate::types::vertex::Vertex;
-use rand::{SeedableRng, seq::SliceRandom};
-use rand_chacha::ChaCha20Rng;
-
-pub type NodeId = u32;
-
-pub fn random_sample<'a>(
-    candidates: &'a [Vertex],
-    d: usize,
-    seed: &[u8],
-) -> Vec<Vertex> {
-    let mut rng = ChaCha20Rng::from_seed(seed[..32].try_into().unwrap());
-    let mut sample = candidates.to_vec();
-    sample.shuffle(&mut rng);
-    sample.into_iter().take(d).collect()
+use crate::crypto::hashing::CanonicalHasher;
+use crate::types::vertex::NodeId;
+
+/// Domain tag for the ranks parents are sampled by.
+const SAMPLE_DOMAIN: &[u8] = b"sparse-bullshark/sample";
+
+/// Picks the sources of the `d` parents of `source`'s vertex in `round` out
+/// of the signers of its sample proof: the `d` signers ranked first by a hash
+/// of the epoch, the round, `source` and the signer.
+///
+/// Nothing the proposer controls goes into the ranks, so it cannot grind
+/// proofs for a sample it likes, at the price of samples being computable
+/// ahead of time. A signer's rank doesn't depend on who else signed: leaving
+/// signers out of the proof (at most f, it needs 2f+1) is all the influence
+/// left, and an honest node has as much when their vertices arrive late.
+pub fn sample_sources(signers: &[NodeId], d: usize, epoch: u64, round: u64, source: NodeId) -> Vec<NodeId> {
+    let mut ranked: Vec<_> = signers.iter()
+        .map(|signer| {
+            let rank = CanonicalHasher::new(SAMPLE_DOMAIN).u64(epoch).u64(round).u32(source).u32(*signer).finish();
+            (rank, *signer)
+        })
+        .collect();
+    ranked.sort_unstable();
+    ranked.dedup_by_key(|(_, signer)| *signer);
+    ranked.into_iter().take(d).map(|(_, signer)| signer).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNERS: [NodeId; 7] = [0, 1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn samples_the_same_sources_every_time() {
+        let sample = sample_sources(&SIGNERS, 3, 1, 10, 2);
+        assert_eq!(sample.len(), 3);
+        assert!(sample.iter().all(|source| SIGNERS.contains(source)));
+        assert_eq!(sample_sources(&SIGNERS, 3, 1, 10, 2), sample);
+        // Neither the order nor repetitions of the signers matter.
+        let mut shuffled = [6, 3, 3, 0, 5, 1, 4, 2, 6];
+        assert_eq!(sample_sources(&shuffled, 3, 1, 10, 2), sample);
+        shuffled.reverse();
+        assert_eq!(sample_sources(&shuffled, 3, 1, 10, 2), sample);
+    }
+
+    #[test]
+    fn leaving_out_an_unsampled_signer_keeps_the_sample() {
+        let sample = sample_sources(&SIGNERS, 3, 1, 10, 2);
+        let others: Vec<NodeId> = SIGNERS.iter().copied().filter(|signer| !sample.contains(signer)).collect();
+        for left_out in others {
+            let signers: Vec<NodeId> = SIGNERS.iter().copied().filter(|signer| *signer != left_out).collect();
+            assert_eq!(sample_sources(&signers, 3, 1, 10, 2), sample);
+        }
+    }
+
+    #[test]
+    fn samples_differ_across_rounds_sources_and_epochs() {
+        let sample = sample_sources(&SIGNERS, 3, 1, 10, 2);
+        assert!((11..30).any(|round| sample_sources(&SIGNERS, 3, 1, round, 2) != sample));
+        assert!((0..7).any(|source| sample_sources(&SIGNERS, 3, 1, 10, source) != sample));
+        assert!((2..20).any(|epoch| sample_sources(&SIGNERS, 3, epoch, 10, 2) != sample));
+    }
+
+    #[test]
+    fn samples_every_signer_when_there_are_no_more_than_d() {
+        let mut sample = sample_sources(&SIGNERS[..2], 3, 1, 10, 2);
+        sample.sort_unstable();
+        assert_eq!(sample, [0, 1]);
+    }
 }